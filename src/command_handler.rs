@@ -1,16 +1,85 @@
-use crate::log_file::{LogFile, SearchDirection};
-use crate::log_viewer::LogViewer;
+use crate::extract;
+use crate::log_file::{DedupMode, FilterRule, GroupRule, LogFile, SearchDirection};
+use crate::log_format::LogFormat;
+use crate::log_viewer::{color_name, format_count, LogViewer};
+use crate::message::Message;
+use crate::query::Query;
+use crate::stats;
+use crate::template::Rarity;
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use log::debug;
-use shlex;
+use regex::Regex;
+
+/// What the controller should do after a command ran
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandAction {
+    /// Move the view to a line
+    Jump(usize),
+    /// Open the message template list
+    ShowPatterns,
+    /// Mark records with rare templates, or stop marking them with `None`
+    SetAnomalies(Option<Rarity>),
+    /// Compare the file side by side with another one
+    Diff(String),
+    /// Show summary statistics, with the top values of an optional field
+    ShowStats(Option<String>),
+    /// Show the numeric series of a field or regex capture, as typed and parsed
+    Extract(String, extract::Source),
+    /// Show the result table of a query
+    Query(String),
+    /// List the marks
+    ShowMarks,
+    /// Annotate the current line, or remove its note when empty
+    Note(String),
+    /// List the notes
+    ShowNotes,
+    /// Write the notes as a markdown timeline to a file
+    ExportNotes(String),
+    /// Save the current session under a name
+    SaveSession(String),
+    /// Replace the current state with a named session
+    LoadSession(String),
+    /// Bind a key sequence to an action or a command
+    Map { keys: String, target: String },
+}
+
+/// Command names and aliases understood by `handle_command`, for completion
+pub const COMMANDS: [&str; 22] = [
+    "hl",
+    "highlight",
+    "hd",
+    "hide",
+    "sh",
+    "show",
+    "marks",
+    "note",
+    "notes",
+    "map",
+    "session",
+    "patterns",
+    "baseline",
+    "stats",
+    "extract",
+    "query",
+    "diff",
+    "anomalies",
+    "dedup",
+    "col",
+    "column",
+    "set",
+];
+
+/// Options of the `set` command
+pub const SET_OPTIONS: [&str; 3] = ["search_color", "group", "format"];
 
 pub fn handle_command(
     input: &str,
     line_num: usize,
     lf: &mut LogFile,
     lv: &mut LogViewer,
-) -> Result<Option<usize>> {
+    formats: &[LogFormat],
+) -> Result<Option<CommandAction>> {
     let trimmed_input = input.trim();
     if trimmed_input.is_empty() {
         return Ok(None);
@@ -20,7 +89,7 @@ pub fn handle_command(
 
     if first_char == '/' || first_char == '?' {
         let parts = shlex::split(&trimmed_input[1..])
-            .ok_or_else(|| anyhow::anyhow!("Failed to parse command"))?;
+            .ok_or_else(|| anyhow!("Failed to parse command"))?;
 
         let ret = search(
             if parts.is_empty() { "" } else { &parts[0] },
@@ -38,12 +107,12 @@ pub fn handle_command(
             &trimmed_input[1..],
             ret
         );
-        return Ok(ret);
+        return Ok(ret.map(CommandAction::Jump));
 
         // Call search function with pattern
     } else {
         let parts = shlex::split(trimmed_input)
-            .ok_or_else(|| anyhow::anyhow!("Failed to parse command"))?;
+            .ok_or_else(|| anyhow!("Failed to parse command"))?;
         let command = &parts[0];
         let args = &parts[1..];
 
@@ -56,59 +125,253 @@ pub fn handle_command(
             "hl" | "highlight" => {
                 // Highlight
                 if args.is_empty() {
-                    return Ok(None); // No pattern provided
+                    return Err(anyhow!("Usage: highlight <pattern> [color]"));
                 }
-                let _color = lv.set_highlight(
-                    args[0].clone(),
-                    if args.len() > 1 {
-                        Some(args[1].to_string())
-                    } else {
-                        None
-                    },
-                );
+                let color = lv.set_highlight(args[0].clone(), args.get(1).cloned())?;
+                lv.set_message(Message::info(format!(
+                    "Highlighting '{}' in {}",
+                    args[0],
+                    color_name(color).unwrap_or_default()
+                )));
             }
             "hd" | "hide"=> {
                 // Hide
                 if args.is_empty() {
-                    return Ok(None); // No pattern provided
+                    return Err(anyhow!("Usage: hide <pattern>"));
                 }
 
-                lf.hide_lines_matching(|line| line.contains(&args[0]));
-
+                let visible = lf.visible_lines();
+                lf.apply_filter(FilterRule::Hide(args[0].clone()))?;
+                let hidden = visible.saturating_sub(lf.visible_lines());
+                lv.set_message(Message::info(format!("Hid {} lines", format_count(hidden))));
             }
             "sh" | "show" => {
-                // Hide
+                // Show only matching lines
                 if args.is_empty() {
-                    return Ok(None); // No pattern provided
+                    return Err(anyhow!("Usage: show <pattern>"));
                 }
 
-                lf.show_lines_matching(|line| line.contains(&args[0]));
+                lf.apply_filter(FilterRule::Show(args[0].clone()))?;
+                lv.set_message(Message::info(format!(
+                    "Showing {} of {} lines",
+                    format_count(lf.visible_lines()),
+                    format_count(lf.total_lines())
+                )));
+            }
+            "marks" => {
+                return Ok(Some(CommandAction::ShowMarks));
+            }
+            "note" => {
+                // Keep the text as typed
+                let text = trimmed_input[command.len()..].trim();
+                return Ok(Some(CommandAction::Note(text.to_string())));
+            }
+            "notes" => {
+                // notes lists them, notes export <path> writes the markdown timeline
+                return Ok(Some(match (args.first().map(String::as_str), args.get(1)) {
+                    (Some("export"), Some(path)) => CommandAction::ExportNotes(path.clone()),
+                    _ => CommandAction::ShowNotes,
+                }));
+            }
+            "map" => {
+                // map <keys> <action|command...>
+                if args.len() < 2 {
+                    return Err(anyhow!("Usage: map <keys> <action|command>"));
+                }
+                return Ok(Some(CommandAction::Map {
+                    keys: args[0].clone(),
+                    target: args[1..].join(" "),
+                }));
+            }
+            "session" => {
+                // session save|load <name>
+                return match (args.first().map(String::as_str), args.get(1)) {
+                    (Some("save"), Some(name)) => Ok(Some(CommandAction::SaveSession(name.clone()))),
+                    (Some("load"), Some(name)) => Ok(Some(CommandAction::LoadSession(name.clone()))),
+                    _ => Err(anyhow!("Usage: session save|load <name>")),
+                };
+            }
+            "patterns" => {
+                return Ok(Some(CommandAction::ShowPatterns));
+            }
+            "baseline" => {
+                // Hide lines that also occur in a known-good log
+                if args.is_empty() {
+                    return Err(anyhow!("Usage: baseline <file>"));
+                }
+                let visible = lf.visible_lines();
+                hide_baseline(lf, &args[0])?;
+                let hidden = visible.saturating_sub(lf.visible_lines());
+                lv.set_message(Message::info(format!(
+                    "Hid {} lines also in {}",
+                    format_count(hidden),
+                    args[0]
+                )));
+            }
+            "stats" => {
+                // stats [field] shows the summary, stats save <path> [field] writes it out
+                if args.first().map(String::as_str) == Some("save") {
+                    if args.len() < 2 {
+                        return Err(anyhow!("Usage: stats save <path> [field]"));
+                    }
+                    let report = stats::summarize(lf, args.get(2).map(String::as_str));
+                    stats::export(&report, &args[1])?;
+                    lv.set_message(Message::info(format!("Wrote stats to {}", args[1])));
+                } else {
+                    return Ok(Some(CommandAction::ShowStats(args.first().cloned())));
+                }
+            }
+            "extract" => {
+                // extract <regex|field> shows the series, extract save <path> <regex|field> writes it as CSV.
+                // Backslashes and spaces belong to the regex, so take it unsplit.
+                let rest = trimmed_input[command.len()..].trim();
+                let (path, pattern) = match first_word(rest) {
+                    ("save", rest) => {
+                        let (path, pattern) = first_word(rest);
+                        (Some(path), pattern)
+                    }
+                    _ => (None, rest),
+                };
+                if pattern.is_empty() {
+                    return Err(anyhow!("Usage: extract [save <path>] <regex|field>"));
+                }
+                let source = extract::Source::parse(pattern, lf.format().is_some())?;
+                match path {
+                    Some(path) => {
+                        let points = extract::extract(lf, &source);
+                        extract::export_csv(&points, path)?;
+                        lv.set_message(Message::info(format!(
+                            "Wrote {} values to {}",
+                            format_count(points.len()),
+                            path
+                        )));
+                    }
+                    None => return Ok(Some(CommandAction::Extract(pattern.to_string(), source))),
+                }
+            }
+            "query" => {
+                // Quotes belong to the query, so take it unsplit
+                let query = trimmed_input[command.len()..].trim();
+                Query::parse(query)?;
+                return Ok(Some(CommandAction::Query(query.to_string())));
+            }
+            "diff" => {
+                if args.is_empty() {
+                    return Err(anyhow!("Usage: diff <file>"));
+                }
+                return Ok(Some(CommandAction::Diff(args[0].clone())));
+            }
+            "anomalies" => {
+                // Mark rare messages: anomalies [count|percent%|off], default 1%
+                let rarity = match args.first().map(String::as_str) {
+                    None => Some(Rarity::Percentile(1.0)),
+                    Some("off") => None,
+                    Some(arg) => Some(
+                        Rarity::parse(arg)
+                            .ok_or_else(|| anyhow!("Invalid anomaly threshold '{}'", arg))?,
+                    ),
+                };
+                return Ok(Some(CommandAction::SetAnomalies(rarity)));
+            }
+            "dedup" => {
+                // Fold consecutive duplicates: dedup [exact|masked|off]
+                let mode = match args.first().map(String::as_str) {
+                    None | Some("exact") => Some(DedupMode::Exact),
+                    Some("masked") => Some(DedupMode::Masked),
+                    Some("off") => None,
+                    Some(other) => return Err(anyhow!("Unknown dedup mode '{}'", other)),
+                };
+                lf.set_dedup(mode);
+                lv.set_message(Message::info(match mode {
+                    Some(DedupMode::Exact) => "Folding repeated lines",
+                    Some(DedupMode::Masked) => "Folding repeated lines, ignoring numbers and ids",
+                    None => "Showing repeated lines",
+                }));
+            }
+            "col" | "column" => {
+                // Table view columns: col hide|show <field>, col width <field> <n>
+                if args.len() < 2 {
+                    return Err(anyhow!("Usage: col hide|show <field>, col width <field> <n>"));
+                }
 
+                let field = &args[1];
+                let done = match args[0].as_str() {
+                    "hide" => lv.set_column_visible(field, false).map(|_| format!("Hid column {}", field)),
+                    "show" => lv.set_column_visible(field, true).map(|_| format!("Showing column {}", field)),
+                    "width" if args.len() > 2 => match args[2].parse::<usize>() {
+                        Ok(width) => lv
+                            .set_column_width(field, width)
+                            .map(|_| format!("Column {} is {} wide", field, width)),
+                        Err(_) => Err(anyhow!("Invalid width: {}", args[2])),
+                    },
+                    _ => Err(anyhow!("Unknown column command: {}", args[0])),
+                };
+                lv.set_message(Message::info(done?));
             }
             "set" => {
-                // Set search pattern
+                // Set an option
                 if args.len() < 2 {
-                    return Ok(None); // No pattern provided
+                    return Err(anyhow!("Usage: set <option> <value>"));
                 }
 
                 match args[0].as_str() {
                     "search_color" => {
-                        lv.set_search_color(args[1].as_str());
+                        lv.set_search_color(args[1].as_str())?;
+                        lv.set_message(Message::info(format!("Search color is {}", args[1])));
+                    }
+                    "group" => {
+                        // Group continuation lines: off, timestamp, format or a start regex
+                        let rule = match args[1].as_str() {
+                            "off" => Some(GroupRule::Off),
+                            "timestamp" => Some(GroupRule::Timestamp),
+                            "format" => lf
+                                .format()
+                                .and_then(|f| f.start_pattern())
+                                .map(|pattern| GroupRule::Pattern(pattern.clone())),
+                            pattern => Regex::new(pattern).ok().map(GroupRule::Pattern),
+                        };
+                        let rule = rule.ok_or_else(|| anyhow!("Invalid group rule '{}'", args[1]))?;
+                        lf.set_group_rule(&rule);
+                        lv.set_message(Message::info(format!("Grouping lines by {}", args[1])));
+                    }
+                    "format" => {
+                        let name = args[1].as_str();
+                        if name == "none" {
+                            lf.clear_format();
+                            lv.set_message(Message::info("Showing lines as plain text"));
+                        } else if lf.set_format_by_name(name, formats) {
+                            lv.set_message(Message::info(format!("Using format {}", name)));
+                        } else {
+                            return Err(anyhow!("Unknown format '{}'", name));
+                        }
                     }
                     _ => {
-                        debug!("Unknown set command: {}", args[0]);
+                        return Err(anyhow!("Unknown option '{}'", args[0]));
                     }
                 }
             }
             _ => {
-                // Unknown command
+                return Err(anyhow!("Unknown command '{}'", command));
             }
         }
     }
-
     Ok(None)
 }
 
+/// Hide every record of `lf` whose normalized message also appears in the
+/// baseline file, unless that baseline is already applied
+pub fn hide_baseline(lf: &mut LogFile, baseline_path: &str) -> Result<()> {
+    let path = std::fs::canonicalize(baseline_path)
+        .with_context(|| format!("Failed to open baseline {}", baseline_path))?;
+    let rule = FilterRule::Baseline(path.to_string_lossy().into_owned());
+    if lf.filters().contains(&rule) {
+        return Ok(());
+    }
+    lf.apply_filter(rule)
+}
+
+/// Search from the prompt, starting at the current line. An empty pattern
+/// repeats the last search in the new direction.
 pub fn search(
     pattern: &str,
     line_num: usize,
@@ -116,17 +379,83 @@ pub fn search(
     lv: &mut LogViewer,
     direction: SearchDirection,
 ) -> Option<usize> {
-    let mut search_current_line = true;
-    let mut pattern = pattern.to_string();
+    lv.search_direction = direction;
     if pattern.is_empty() {
-        if let Some(val) = &lv.search_pattern {
-            pattern = val.clone();
-            search_current_line = false;
-        } else {
-            return None; // No search pattern to clear
-        }
+        return search_next(line_num, lf, lv, false);
     }
 
     lv.search_pattern = Some(pattern.to_string());
-    lf.search(&pattern, line_num, search_current_line, direction)
+    find_wrapping(pattern, line_num, true, lf, lv, direction)
+}
+
+/// `n`: the next match of the last search in its direction; `N` (`reverse`)
+/// the other way
+pub fn search_next(line_num: usize, lf: &LogFile, lv: &mut LogViewer, reverse: bool) -> Option<usize> {
+    let Some(pattern) = lv.search_pattern.clone() else {
+        lv.set_message(Message::error("No previous search"));
+        return None;
+    };
+    let direction = if reverse {
+        lv.search_direction.reversed()
+    } else {
+        lv.search_direction
+    };
+    find_wrapping(&pattern, line_num, false, lf, lv, direction)
+}
+
+/// Split off the first whitespace separated word of `s`
+fn first_word(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    match s.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (s, ""),
+    }
+}
+
+/// Search on from `line_num`, then from the other end of the file
+fn find_wrapping(
+    pattern: &str,
+    line_num: usize,
+    search_current_line: bool,
+    lf: &LogFile,
+    lv: &mut LogViewer,
+    direction: SearchDirection,
+) -> Option<usize> {
+    let found = lf
+        .search_wrapping(pattern, line_num, search_current_line, direction, || false)
+        .unwrap_or(None);
+    let end = match direction {
+        SearchDirection::Forward => "top",
+        SearchDirection::Backward => "bottom",
+    };
+    match found {
+        Some((_, false)) => {}
+        Some((_, true)) => lv.set_message(Message::warn(format!("Search wrapped around to the {}", end))),
+        None => lv.set_message(Message::warn(format!("Pattern not found: {}", pattern))),
+    }
+    found.map(|(line, _)| line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_file::testing::open_test_log;
+
+    #[test]
+    fn test_extract_takes_raw_regex() {
+        let (file, mut lf) = open_test_log("request took 12ms\n");
+        let mut lv = LogViewer::new();
+
+        let action = handle_command(r"extract took (\d+)ms", 0, &mut lf, &mut lv, &[]).unwrap();
+        let source = extract::Source::parse(r"took (\d+)ms", false).unwrap();
+        assert_eq!(action, Some(CommandAction::Extract(r"took (\d+)ms".to_string(), source)));
+
+        let csv = file.path().with_extension("csv");
+        let input = format!(r"extract save {} took (\d+)", csv.display());
+        assert_eq!(handle_command(&input, 0, &mut lf, &mut lv, &[]).unwrap(), None);
+        assert!(std::fs::read_to_string(&csv).unwrap().contains(",12\n"));
+        std::fs::remove_file(csv).unwrap();
+
+        assert!(handle_command("extract save out.csv", 0, &mut lf, &mut lv, &[]).is_err());
+    }
 }
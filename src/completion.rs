@@ -0,0 +1,213 @@
+use std::fs;
+
+use crate::command_handler::{COMMANDS, SET_OPTIONS};
+use crate::log_file::LogFile;
+use crate::log_format::{self, LogFormat};
+
+/// Names accepted by `Color::try_from`
+const COLOR_NAMES: [&str; 16] = [
+    "black",
+    "dark_grey",
+    "red",
+    "dark_red",
+    "green",
+    "dark_green",
+    "yellow",
+    "dark_yellow",
+    "blue",
+    "dark_blue",
+    "magenta",
+    "dark_magenta",
+    "cyan",
+    "dark_cyan",
+    "white",
+    "grey",
+];
+
+/// What the open file offers for completion
+#[derive(Debug, Default)]
+pub struct Context {
+    /// Field names seen in the first lines of a structured log
+    pub fields: Vec<String>,
+    pub formats: Vec<String>,
+}
+
+impl Context {
+    pub fn new(lf: &LogFile, user_formats: &[LogFormat]) -> Context {
+        let sample = lf.sample_lines(log_format::SAMPLE_LINES);
+
+        let mut fields: Vec<String> = Vec::new();
+        if let Some(format) = lf.format() {
+            for record in sample.iter().filter_map(|line| format.parse(line)) {
+                for (name, _) in record.fields {
+                    if !fields.contains(&name) {
+                        fields.push(name);
+                    }
+                }
+            }
+        }
+
+        let formats = log_format::all_formats(user_formats, &sample)
+            .iter()
+            .map(|format| format.name().to_string())
+            .collect();
+        Context { fields, formats }
+    }
+}
+
+/// Candidates for the word before the cursor
+#[derive(Debug, PartialEq)]
+pub struct Completion {
+    /// Character offset where the word being completed starts
+    pub start: usize,
+    pub candidates: Vec<String>,
+}
+
+impl Completion {
+    /// Longest prefix shared by every candidate
+    pub fn common_prefix(&self) -> String {
+        let Some(first) = self.candidates.first() else {
+            return String::new();
+        };
+        let mut prefix: Vec<char> = first.chars().collect();
+        for candidate in &self.candidates[1..] {
+            let shared = prefix.iter().zip(candidate.chars()).take_while(|(a, b)| **a == *b).count();
+            prefix.truncate(shared);
+        }
+        prefix.into_iter().collect()
+    }
+}
+
+/// Complete the last word of `input`, the prompt text up to the cursor
+pub fn complete(input: &str, context: &Context) -> Completion {
+    let start = input
+        .char_indices()
+        .rev()
+        .find(|(_, c)| c.is_whitespace())
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let word = &input[start..];
+    let args: Vec<&str> = input[..start].split_whitespace().collect();
+
+    let options = match args.split_first() {
+        // Searches take free-form patterns
+        _ if input.starts_with(['/', '?']) => Vec::new(),
+        None => to_strings(&COMMANDS),
+        Some((command, args)) => argument_options(command, args, word, context),
+    };
+
+    Completion {
+        start: input[..start].chars().count(),
+        candidates: options.into_iter().filter(|o| o.starts_with(word)).collect(),
+    }
+}
+
+/// What may follow `command` and the arguments before the word being completed
+fn argument_options(command: &str, args: &[&str], word: &str, context: &Context) -> Vec<String> {
+    let fields = || context.fields.clone();
+    match (command, args) {
+        ("hl" | "highlight" | "hd" | "hide" | "sh" | "show", []) => context
+            .fields
+            .iter()
+            .map(|field| format!("@{}=", field))
+            .collect(),
+        ("hl" | "highlight", [_]) => to_strings(&COLOR_NAMES),
+        ("set", []) => to_strings(&SET_OPTIONS),
+        ("set", ["search_color"]) => to_strings(&COLOR_NAMES),
+        ("set", ["group"]) => to_strings(&["off", "timestamp", "format"]),
+        ("set", ["format"]) => {
+            let mut formats = context.formats.clone();
+            formats.push("none".to_string());
+            formats
+        }
+        ("col" | "column", []) => to_strings(&["hide", "show", "width"]),
+        ("col" | "column", [_]) => fields(),
+        ("stats" | "extract", []) => {
+            let mut options = vec!["save".to_string()];
+            options.extend(fields());
+            options
+        }
+        ("stats" | "extract", ["save"]) => path_options(word),
+        ("stats" | "extract", ["save", _]) => fields(),
+        ("notes", []) => to_strings(&["export"]),
+        ("notes", ["export"]) => path_options(word),
+        ("session", []) => to_strings(&["save", "load"]),
+        ("baseline" | "diff", []) => path_options(word),
+        ("dedup", []) => to_strings(&["exact", "masked", "off"]),
+        ("anomalies", []) => to_strings(&["off"]),
+        ("query", _) => fields(),
+        _ => Vec::new(),
+    }
+}
+
+/// Entries of the directory named by `word` up to its last `/`; directories end in `/`
+fn path_options(word: &str) -> Vec<String> {
+    let (dir, name) = match word.rfind('/') {
+        Some(i) => word.split_at(i + 1),
+        None => ("", word),
+    };
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+
+    let mut options: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            // Hidden files only when asked for
+            if file_name.starts_with('.') && !name.starts_with('.') {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, file_name, slash))
+        })
+        .collect();
+    options.sort();
+    options
+}
+
+fn to_strings(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete() {
+        let context = Context {
+            fields: vec!["level".to_string(), "latency".to_string(), "msg".to_string()],
+            formats: vec!["json".to_string()],
+        };
+        let candidates = |input| complete(input, &context).candidates;
+
+        assert_eq!(candidates("hi"), vec!["highlight", "hide"]);
+        assert_eq!(candidates("set sea"), vec!["search_color"]);
+        assert_eq!(candidates("set search_color dark_r"), vec!["dark_red"]);
+        assert_eq!(candidates("hide @l"), vec!["@level=", "@latency="]);
+        assert_eq!(candidates("set format "), vec!["json", "none"]);
+        assert!(candidates("/hi").is_empty());
+
+        let completion = complete("stats save out.txt la", &context);
+        assert_eq!(completion.start, 19);
+        assert_eq!(completion.candidates, vec!["latency"]);
+
+        let completion = complete("col show l", &context);
+        assert_eq!(completion.common_prefix(), "l");
+
+        // Wide spaces split words too
+        let completion = complete("hide\u{3000}@l", &context);
+        assert_eq!(completion.start, 5);
+        assert_eq!(completion.candidates, vec!["@level=", "@latency="]);
+        assert_eq!(complete("set\u{a0}", &context).start, 4);
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("logs")).unwrap();
+        fs::write(dir.path().join("good.log"), "").unwrap();
+        let base = format!("{}/", dir.path().display());
+        assert_eq!(
+            candidates(&format!("baseline {}", base)),
+            vec![format!("{}good.log", base), format!("{}logs/", base)]
+        );
+    }
+}
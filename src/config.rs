@@ -0,0 +1,214 @@
+use anyhow::{anyhow, Context, Result};
+use crossterm::style::Color;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::keymap;
+use crate::paths;
+use crate::status;
+
+/// Default number of screen rows a long line may wrap onto
+pub const DEFAULT_WRAP_LIMIT: usize = 3;
+
+/// Settings read from `config.toml` in the config dir. Every key is optional.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Colors handed out to highlights without an explicit color, first one
+    /// first; the built-in palette when absent
+    pub palette: Option<Vec<String>>,
+    /// Most screen rows a long line wraps onto before being cut
+    pub wrap_limit: usize,
+    /// Status bar layout with `{name}` placeholders, see `status::render`
+    pub status_format: String,
+    /// Where the debug log goes; defaults to `debug.log` in the data dir
+    pub debug_log: Option<PathBuf>,
+    #[serde(rename = "highlight")]
+    pub highlights: Vec<HighlightRule>,
+    #[serde(rename = "filter")]
+    pub filters: Vec<GlobFilter>,
+    /// Commands run after a file is opened, as typed at the prompt
+    pub startup: Vec<String>,
+    /// Key sequence (e.g. `gg`, `<C-d>`) to action name or command
+    pub keys: BTreeMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            palette: None,
+            wrap_limit: DEFAULT_WRAP_LIMIT,
+            status_format: status::DEFAULT_FORMAT.to_string(),
+            debug_log: None,
+            highlights: Vec::new(),
+            filters: Vec::new(),
+            startup: Vec::new(),
+            keys: BTreeMap::new(),
+        }
+    }
+}
+
+/// Highlight applied to every file that has no saved session
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HighlightRule {
+    pub pattern: String,
+    pub color: Option<String>,
+}
+
+/// Filters applied to files whose name matches a glob, unless a session is restored
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GlobFilter {
+    /// `*` and `?` wildcards, matched against the file name, or the whole
+    /// path when the glob contains a `/`
+    pub glob: String,
+    #[serde(default)]
+    pub hide: Vec<String>,
+    #[serde(default)]
+    pub show: Vec<String>,
+}
+
+impl GlobFilter {
+    pub fn matches(&self, path: &Path) -> bool {
+        let target = if self.glob.contains('/') {
+            path.to_string_lossy()
+        } else {
+            path.file_name().unwrap_or_default().to_string_lossy()
+        };
+        glob_regex(&self.glob).is_ok_and(|regex| regex.is_match(&target))
+    }
+}
+
+/// `**` matches anything, `*` anything but `/`, `?` one character but `/`
+fn glob_regex(glob: &str) -> Result<Regex> {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Ok(Regex::new(&regex)?)
+}
+
+impl Config {
+    pub fn default_path() -> Option<PathBuf> {
+        paths::config_dir().map(|dir| dir.join("config.toml"))
+    }
+
+    /// Load the config at `path`, or the defaults when there is no file there
+    pub fn load(path: &Path) -> Result<Config> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let config = Config::parse(&content)
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        Ok(config)
+    }
+
+    pub fn parse(content: &str) -> Result<Config> {
+        let config: Config = toml::from_str(content)?;
+
+        config.palette()?;
+        if config.wrap_limit == 0 {
+            return Err(anyhow!("wrap_limit must be at least 1"));
+        }
+        status::validate(&config.status_format)?;
+        for rule in &config.highlights {
+            if let Some(color) = &rule.color {
+                parse_color(color)?;
+            }
+        }
+        for keys in config.keys.keys() {
+            keymap::parse_keys(keys).with_context(|| format!("Invalid key binding '{}'", keys))?;
+        }
+        for filter in &config.filters {
+            glob_regex(&filter.glob).with_context(|| format!("Invalid glob '{}'", filter.glob))?;
+        }
+        Ok(config)
+    }
+
+    pub fn palette(&self) -> Result<Option<Vec<Color>>> {
+        self.palette
+            .as_ref()
+            .map(|palette| palette.iter().map(|c| parse_color(c)).collect())
+            .transpose()
+    }
+
+    /// Debug log path, falling back to the data dir
+    pub fn debug_log_path(&self) -> Option<PathBuf> {
+        self.debug_log
+            .clone()
+            .or_else(|| paths::data_dir().map(|dir| dir.join("debug.log")))
+    }
+
+    /// Filters for the file at `path`, as hide/show rules in order
+    pub fn filters_for<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = &'a GlobFilter> {
+        self.filters.iter().filter(move |f| f.matches(path))
+    }
+}
+
+fn parse_color(name: &str) -> Result<Color> {
+    Color::try_from(name).map_err(|_| anyhow!("Unknown color '{}'", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            r#"
+            palette = ["red", "dark_cyan"]
+            wrap_limit = 5
+            status_format = "{file} {line}/{total}"
+            startup = ["dedup masked"]
+
+            [keys]
+            "<C-d>" = "page-down"
+
+            [[highlight]]
+            pattern = "@level=error"
+            color = "red"
+
+            [[filter]]
+            glob = "*access*.log"
+            hide = ["healthcheck"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.palette().unwrap(), Some(vec![Color::Red, Color::DarkCyan]));
+        assert_eq!(config.wrap_limit, 5);
+        assert_eq!(config.status_format, "{file} {line}/{total}");
+        assert_eq!(config.highlights[0].pattern, "@level=error");
+        assert_eq!(config.startup, vec!["dedup masked"]);
+        assert_eq!(config.filters_for(Path::new("/var/log/nginx/access.1.log")).count(), 1);
+        assert_eq!(config.filters_for(Path::new("access.log.gz")).count(), 0);
+
+        let defaults = Config::parse("").unwrap();
+        assert_eq!(defaults.wrap_limit, DEFAULT_WRAP_LIMIT);
+        assert_eq!(defaults.palette().unwrap(), None);
+
+        let err = Config::parse("wrap_limt = 3").unwrap_err();
+        assert!(format!("{:#}", err).contains("unknown field `wrap_limt`"));
+        let err = Config::parse("palette = [\"reddish\"]").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown color 'reddish'");
+        assert!(Config::parse("status_format = \"{bogus}\"").is_err());
+        assert!(Config::parse("[keys]\n\"<Nope>\" = \"quit\"").is_err());
+        assert!(Config::parse("[[filter]]\nglob = \"*\"\nhidden = []").is_err());
+    }
+}
@@ -1,13 +1,61 @@
-use crate::command_handler::handle_command;
-use crate::log_file;
-use crate::log_viewer;
+use crate::command_handler::{self, handle_command, hide_baseline, CommandAction};
+use crate::completion::{self, Completion};
+use crate::config::Config;
+use crate::diff;
+use crate::extract;
+use crate::history::History;
+use crate::keymap::{Action, Binding, Key, Keymap, Lookup};
+use crate::line_editor::LineEditor;
+use crate::log_file::{self, FilterRule, Line, SearchDirection};
+use crate::log_format::{Level, LogFormat};
+use crate::log_viewer::{self, GutterMarker};
+use crate::message::Message;
+use crate::notes::Notes;
+use crate::overlay::ListOverlay;
+use crate::query::Query;
+use crate::session::{self, Session};
+use crate::stats;
+use crate::status::{self, StatusInfo};
+use crate::template::{self, Template};
+use crate::timeline::Timeline;
 use anyhow::Result;
-use crossterm::event::{self, Event, KeyCode, KeyEvent};
+use crossterm::event::{
+    self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyModifiers,
+};
+use crossterm::execute;
+use crossterm::style::Color;
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::io::Write;
 use tempfile::NamedTempFile;
 
-use log::debug;
+use log::{debug, info};
+
+/// Order of the entries in the patterns overlay
+#[derive(Clone, Copy)]
+enum PatternSort {
+    Count,
+    First,
+    Last,
+}
+
+impl PatternSort {
+    fn next(self) -> Self {
+        match self {
+            PatternSort::Count => PatternSort::First,
+            PatternSort::First => PatternSort::Last,
+            PatternSort::Last => PatternSort::Count,
+        }
+    }
+
+    fn sort(self, templates: &mut [Template]) {
+        match self {
+            PatternSort::Count => templates.sort_by_key(|t| std::cmp::Reverse(t.count())),
+            PatternSort::First => templates.sort_by_key(|t| t.first()),
+            PatternSort::Last => templates.sort_by_key(|t| std::cmp::Reverse(t.last())),
+        }
+    }
+}
 
 enum ViewMode {
     Normal,
@@ -20,6 +68,46 @@ struct ViewState {
     cursor: (u16, u16),
 }
 
+/// Lines scanned for search matches each time the event loop is idle
+const COUNT_CHUNK_LINES: usize = 50_000;
+
+/// Matches of the search pattern, counted a chunk at a time while idle
+struct MatchCount {
+    pattern: String,
+    matches: Vec<usize>,
+    /// Record to carry on counting from, past the last line when done
+    next: usize,
+}
+
+/// History `name`, or an empty one kept in memory when the file cannot be read
+fn open_history(name: &str, log_viewer: &mut log_viewer::LogViewer) -> History {
+    History::open(name).unwrap_or_else(|e| {
+        log_viewer.set_message(Message::warn(format!("Failed to load {} history: {:#}", name, e)));
+        History::default()
+    })
+}
+
+/// Raw mode and bracketed paste for as long as it lives, so the terminal is
+/// restored however `run` returns
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enable() -> Result<TerminalGuard> {
+        enable_raw_mode()?;
+        let guard = TerminalGuard;
+        execute!(std::io::stdout(), EnableBracketedPaste)?;
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        // Nothing left to report errors to
+        let _ = execute!(std::io::stdout(), DisableBracketedPaste);
+        let _ = disable_raw_mode();
+    }
+}
+
 pub struct Controller {
     log_file: log_file::LogFile,
     log_viewer: log_viewer::LogViewer,
@@ -34,15 +122,54 @@ pub struct Controller {
     expanded_log_file: Option<log_file::LogFile>,
     mode: ViewMode,
     normal_view_state: ViewState,
+    file_name: String,
+    formats: Vec<LogFormat>,
+    anomalies: Option<BTreeSet<usize>>,
+    timeline: Option<Timeline>,
+    /// Named marks (a-z) on original line numbers
+    marks: BTreeMap<char, usize>,
+    notes: Notes,
+    log_path: std::path::PathBuf,
+    /// Commands from the config, run once the screen is up
+    startup: Vec<String>,
+    keymap: Keymap,
+    /// Keys typed so far of a multi-key binding
+    pending_keys: Vec<Key>,
+    /// Patterns entered at the `/` and `?` prompts
+    search_history: History,
+    command_history: History,
+    status_format: String,
+    /// Records matching the search pattern, for the status bar
+    search_matches: Option<MatchCount>,
 }
 
 impl Controller {
-    pub fn new(log_file_path: &str) -> anyhow::Result<Self> {
-        let log_file = log_file::LogFile::new(log_file_path)?;
-        let log_viewer = log_viewer::LogViewer::new();
+    pub fn new(
+        log_file_path: &str,
+        formats: Vec<LogFormat>,
+        baseline: Option<&str>,
+        config: Config,
+    ) -> anyhow::Result<Self> {
+        let mut log_file = log_file::LogFile::new(log_file_path)?;
+        log_file.detect_format(&formats);
+        if let Some(format) = log_file.format() {
+            info!("Using log format '{}' for {}", format.name(), log_file_path);
+        }
+        let (notes, notes_error) = match Notes::load(std::path::Path::new(log_file_path), &log_file) {
+            Ok(notes) => (notes, None),
+            Err(e) => (Notes::in_memory(&log_file), Some(e)),
+        };
+
+        let mut log_viewer = log_viewer::LogViewer::new();
+        log_viewer.set_wrap_limit(config.wrap_limit);
+        if let Some(palette) = config.palette()? {
+            log_viewer.set_palette(&palette);
+        }
         let (rows, cols) = log_viewer.get_row_cols()?;
+        let search_history = open_history("search", &mut log_viewer);
+        let command_history = open_history("commands", &mut log_viewer);
 
-        Ok(Controller {
+        let mut controller = Controller {
             log_file,
             log_viewer,
             running: true,
@@ -60,25 +187,187 @@ impl Controller {
                 end_line: 0,
                 cursor: (0, 0),
             },
-        })
+            file_name: std::path::Path::new(log_file_path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| log_file_path.to_string()),
+            formats,
+            anomalies: None,
+            marks: BTreeMap::new(),
+            notes,
+            timeline: None,
+            log_path: std::path::PathBuf::from(log_file_path),
+            startup: config.startup.clone(),
+            keymap: Keymap::default(),
+            pending_keys: Vec::new(),
+            search_history,
+            command_history,
+            status_format: config.status_format.clone(),
+            search_matches: None,
+        };
+        for (keys, target) in &config.keys {
+            controller.keymap.map(keys, target)?;
+        }
+        if let Some(e) = notes_error {
+            let text = format!("Failed to load notes, new ones will not be saved: {:#}", e);
+            controller.log_viewer.set_message(Message::warn(text));
+        }
+
+        // Pick up where the last session on this file left off, or start
+        // from the configured defaults
+        match session::auto_path(&controller.log_path).and_then(|path| Session::load(&path)) {
+            Ok(Some(saved)) => controller.restore_session(saved)?,
+            Ok(None) => controller.apply_config_defaults(&config),
+            Err(e) => {
+                let text = format!("Failed to restore session: {:#}", e);
+                controller.log_viewer.set_message(Message::warn(text));
+                controller.apply_config_defaults(&config);
+            }
+        }
+        if let Some(baseline) = baseline {
+            hide_baseline(&mut controller.log_file, baseline)?;
+        }
+
+        Ok(controller)
+    }
+
+    /// Highlights and per-glob filters from the config
+    fn apply_config_defaults(&mut self, config: &Config) {
+        for rule in &config.highlights {
+            if let Err(e) = self.log_viewer.set_highlight(rule.pattern.clone(), rule.color.clone()) {
+                self.log_viewer.set_message(Message::warn(format!("Highlight '{}': {}", rule.pattern, e)));
+            }
+        }
+        for filter in config.filters_for(&self.log_path) {
+            let hide = filter.hide.iter().cloned().map(FilterRule::Hide);
+            let show = filter.show.iter().cloned().map(FilterRule::Show);
+            for rule in hide.chain(show) {
+                if let Err(e) = self.log_file.apply_filter(rule) {
+                    self.log_viewer.set_message(Message::warn(format!("Filter: {:#}", e)));
+                }
+            }
+        }
+    }
+
+    /// Run the startup commands from the config
+    fn run_startup_commands(&mut self) -> Result<()> {
+        for command in std::mem::take(&mut self.startup) {
+            let result = handle_command(
+                &command,
+                self.get_current_line_number(),
+                &mut self.log_file,
+                &mut self.log_viewer,
+                &self.formats,
+            )
+            .and_then(|action| self.handle_action(action));
+            if let Err(e) = result {
+                let text = format!("Startup command '{}': {:#}", command, e);
+                self.log_viewer.set_message(Message::error(text));
+            }
+        }
+        self.refresh_timeline()
+    }
+
+    /// Current filters, highlights, search, marks and position
+    fn session(&self) -> Session {
+        let (start_line, cursor) = match self.mode {
+            ViewMode::Normal => (self.start_line, self.cursor),
+            ViewMode::Expanded => (self.normal_view_state.start_line, self.normal_view_state.cursor),
+        };
+        Session {
+            format: self.log_file.format().map(|format| format.name().to_string()),
+            dedup: self.log_file.dedup(),
+            filters: self.log_file.filters().to_vec(),
+            highlights: self.log_viewer.highlights(),
+            search: self.log_viewer.search_pattern.clone(),
+            marks: self.marks.clone(),
+            start_line,
+            cursor,
+        }
+    }
+
+    /// Replace the current state with a saved session, replaying its filters
+    fn restore_session(&mut self, session: Session) -> Result<()> {
+        if matches!(self.mode, ViewMode::Expanded) {
+            self.switch_to_normal_mode();
+        }
+
+        if let Some(format) = &session.format {
+            if !self.log_file.set_format_by_name(format, &self.formats) {
+                self.log_viewer.set_message(Message::warn(format!("Unknown format in session: {}", format)));
+            }
+        }
+        self.log_file.show_all();
+        self.log_file.set_dedup(session.dedup);
+        for rule in session.filters {
+            if let Err(e) = self.log_file.apply_filter(rule) {
+                self.log_viewer.set_message(Message::warn(format!("Failed to restore filter: {:#}", e)));
+            }
+        }
+
+        self.log_viewer.clear_highlights();
+        for (pattern, color) in session.highlights {
+            if let Err(e) = self.log_viewer.set_highlight(pattern.clone(), Some(color)) {
+                self.log_viewer.set_message(Message::warn(format!("Highlight '{}': {}", pattern, e)));
+            }
+        }
+        self.log_viewer.search_pattern = session.search;
+
+        let total_lines = self.log_file.total_lines();
+        self.marks = session.marks;
+        self.marks.retain(|_, line| *line < total_lines);
+
+        self.start_line = session.start_line.min(total_lines.saturating_sub(1));
+        self.end_line = (self.start_line + self.rows).min(total_lines);
+        self.cursor = session.cursor;
+        self.log_viewer.set_cursor(self.cursor.0, self.cursor.1)?;
+        self.refresh_timeline()
+    }
+
+    /// Save the session under a name, or as the file's automatic session with `None`
+    fn save_session(&self, name: Option<&str>) -> Result<()> {
+        let path = match name {
+            Some(name) => session::named_path(name)?,
+            None => session::auto_path(&self.log_path)?,
+        };
+        self.session().save(&path)
+    }
+
+    fn load_session(&mut self, name: &str) -> Result<()> {
+        match Session::load(&session::named_path(name)?)? {
+            Some(saved) => self.restore_session(saved),
+            None => Err(anyhow::anyhow!("No session named {}", name)),
+        }
     }
 
     pub fn run(&mut self) -> anyhow::Result<()> {
         let (rows, _) = self.log_viewer.get_row_cols()?;
-        self.start_line = 0;
-        self.end_line = rows;
+        // start_line may come from a restored session
+        self.end_line = self.start_line + rows;
 
+        let terminal = TerminalGuard::enable()?;
+        self.run_startup_commands()?;
         self.draw()?;
-        enable_raw_mode()?;
 
         while self.running {
             let mut redraw = false;
 
-            // Check for events with timeout
-            if event::poll(std::time::Duration::from_millis(100))? {
+            // Check for events with timeout, or just check while search
+            // matches are left to count
+            let timeout = if self.counting_matches() { 0 } else { 100 };
+            if event::poll(std::time::Duration::from_millis(timeout))? {
                 match event::read()? {
                     Event::Key(key) => {
-                        redraw = self.handle_key_event(key)?;
+                        // Messages stay up until the next key
+                        let had_message = self.log_viewer.clear_message();
+                        redraw = match self.handle_key_event(key) {
+                            Ok(redraw) => redraw,
+                            Err(e) => {
+                                self.log_viewer.set_message(Message::error(format!("{:#}", e)));
+                                true
+                            }
+                        };
+                        redraw |= had_message || self.log_viewer.message().is_some();
                     }
                     Event::Resize(width, height) => {
                         self.handle_resize(width, height)?;
@@ -91,10 +380,17 @@ impl Controller {
                 if redraw && self.running {
                     self.draw()?;
                 }
+            } else if self.counting_matches() {
+                self.count_matches();
+                self.draw_status()?;
             }
         }
 
-        disable_raw_mode()?;
+        drop(terminal);
+
+        if let Err(e) = self.save_session(None) {
+            debug!("Failed to save session: {}", e);
+        }
 
         Ok(())
     }
@@ -123,16 +419,20 @@ impl Controller {
 
         // Create temp file and write the line to it
         let mut temp_file = NamedTempFile::new()?;
-        let line_content = self
+        let record = self
             .log_file
-            .get_line(self.get_current_line_number())
+            .get_record(self.get_current_line_number())
             .unwrap_or_default();
 
-        let bytes = line_content.as_bytes();
-
-        for chunk in bytes.chunks(self.cols) {
-            temp_file.write_all(chunk)?;
-            temp_file.write_all(b"\n")?; // Add newline after each chunk
+        for line in record.lines() {
+            let bytes = line.as_bytes();
+            if bytes.is_empty() {
+                temp_file.write_all(b"\n")?;
+            }
+            for chunk in bytes.chunks(self.cols) {
+                temp_file.write_all(chunk)?;
+                temp_file.write_all(b"\n")?; // Add newline after each chunk
+            }
         }
 
         let temp_path = temp_file.path().to_str().unwrap().to_string();
@@ -169,103 +469,360 @@ impl Controller {
         self.temp_file = None;
     }
 
-    fn command_mode(&mut self, key: Option<char>) -> Result<Option<usize>> {
-        self.log_viewer.set_cursor_to_command_line()?;
-        
-        let mut input = String::new();
-
-        if let Some(c) = key {
-            input.push(c);
-            print!("{}", c);
-            std::io::stdout().flush()?;
-        }
+    fn command_mode(&mut self, key: Option<char>) -> Result<Option<CommandAction>> {
+        let lead = key.map(String::from).unwrap_or_default();
+        let search = matches!(key, Some('/' | '?'));
+        let mut editor = LineEditor::new(&lead);
+        // Searches jump to the first match as the pattern is typed, starting
+        // from where the prompt was opened each time
+        let incremental = search && matches!(self.mode, ViewMode::Normal);
+        let origin = ViewState {
+            start_line: self.start_line,
+            end_line: self.end_line,
+            cursor: self.cursor,
+        };
+        let origin_line = self.get_current_line_number();
+        let origin_pattern = self.log_viewer.search_pattern.clone();
+        let direction = if key == Some('?') {
+            SearchDirection::Backward
+        } else {
+            SearchDirection::Forward
+        };
+        let mut previewed = lead.clone();
+        // History entry shown by Up/Down, and the typed text it is filtered by
+        let mut browsing: Option<(usize, String)> = None;
+        // Candidates listed by Tab, and the one inserted
+        let mut completion: Option<(Completion, Option<usize>)> = None;
 
         loop {
-            if let Event::Key(KeyEvent { code, .. }) = event::read()? {
-                match code {
-                    KeyCode::Char(c) => {
-                        input.push(c);
-                        print!("{}", c);
-                        std::io::stdout().flush()?;
+            if incremental && editor.text() != previewed {
+                let text = editor.text();
+                let preview = text
+                    .strip_prefix(lead.as_str())
+                    .filter(|pattern| !pattern.is_empty())
+                    .map(|pattern| (pattern, direction));
+                // A cancelled preview is tried again after the key that cancelled it
+                if self.preview_search(preview, origin_line, &origin, &origin_pattern)? {
+                    previewed = text;
+                }
+            }
+            self.log_viewer.print_prompt(&mut editor)?;
+
+            match event::read()? {
+                Event::Key(key) => {
+                    if !matches!(key.code, KeyCode::Tab | KeyCode::BackTab) && completion.take().is_some() {
+                        // Put the status bar back
+                        self.draw()?;
                     }
-                    KeyCode::Backspace => {
-                        if !input.is_empty() {
-                            input.pop();
-                            print!(" ");
-                            std::io::stdout().flush()?;
+                    match key.code {
+                        KeyCode::Enter => {
+                            self.log_viewer.clear_command_line()?;
+                            let input = editor.text();
+                            self.add_to_history(&input);
+                            // Search from where the prompt was opened, not from the preview
+                            let line_num = if incremental {
+                                self.restore_view(&origin)?;
+                                self.log_viewer.search_pattern = origin_pattern;
+                                origin_line
+                            } else {
+                                self.get_current_line_number()
+                            };
+                            return handle_command(
+                                &input,
+                                line_num,
+                                &mut self.log_file,
+                                &mut self.log_viewer,
+                                &self.formats,
+                            );
+                        }
+                        KeyCode::Esc => {
+                            self.log_viewer.clear_command_line()?;
+                            if incremental {
+                                self.restore_view(&origin)?;
+                                self.log_viewer.search_pattern = origin_pattern;
+                            }
+                            break;
+                        }
+                        KeyCode::Up | KeyCode::Down => {
+                            let history = self.history(search);
+                            let (pos, prefix) = browsing.take().unwrap_or_else(|| {
+                                let text = editor.text();
+                                let typed = text.strip_prefix(lead.as_str()).unwrap_or(&text);
+                                (history.len(), typed.to_string())
+                            });
+                            let found = if key.code == KeyCode::Up {
+                                history.prev(&prefix, pos)
+                            } else {
+                                history.next(&prefix, pos)
+                            };
+                            match found {
+                                Some(i) => {
+                                    editor.set_text(&format!("{}{}", lead, history.get(i).unwrap_or_default()));
+                                    browsing = Some((i, prefix));
+                                }
+                                // Down past the newest match goes back to what was typed
+                                None if key.code == KeyCode::Down => {
+                                    editor.set_text(&format!("{}{}", lead, prefix));
+                                }
+                                None => browsing = Some((pos, prefix)),
+                            }
+                        }
+                        KeyCode::Tab | KeyCode::BackTab => {
+                            let backward = key.code == KeyCode::BackTab;
+                            self.complete(&mut editor, &mut completion, backward)?;
+                        }
+                        KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            if let Some(entry) = self.reverse_search(search)? {
+                                editor.set_text(&format!("{}{}", lead, entry));
+                            }
+                            browsing = None;
+                        }
+                        _ => {
+                            if editor.handle_key(key) {
+                                browsing = None;
+                            }
                         }
                     }
-                    KeyCode::Enter => {
-                        self.log_viewer.clear_command_line()?;
-                        return handle_command(
-                            &input,
-                            self.get_current_line_number(),
-                            &mut self.log_file,
-                            &mut self.log_viewer,
-                        );
-                    }
-                    KeyCode::Esc => {
-                        self.log_viewer.clear_command_line()?;
-                        break;
-                    }
-                    _ => {}
                 }
+                Event::Paste(text) => {
+                    editor.insert_str(&text);
+                    browsing = None;
+                }
+                _ => {}
             }
         }
         Ok(None)
     }
 
+    /// Jump to the match Enter would go to for the pattern being typed.
+    /// The search starts at `origin_line` and wraps around the file.
+    /// Every match of the pattern is highlighted.
+    /// `None` goes back to the view the prompt was opened on.
+    /// Returns false when a key press cut the search short.
+    fn preview_search(
+        &mut self,
+        preview: Option<(&str, SearchDirection)>,
+        origin_line: usize,
+        origin: &ViewState,
+        origin_pattern: &Option<String>,
+    ) -> Result<bool> {
+        match preview {
+            Some((pattern, direction)) => {
+                let found = self.log_file.search_wrapping(pattern, origin_line, true, direction, || {
+                    event::poll(std::time::Duration::ZERO).unwrap_or(false)
+                });
+                let Ok(found) = found else {
+                    return Ok(false);
+                };
+                self.log_viewer.search_pattern = Some(pattern.to_string());
+                match found {
+                    Some((line, _)) => self.jump_to_line(line)?,
+                    None => self.restore_view(origin)?,
+                }
+            }
+            None => {
+                self.log_viewer.search_pattern = origin_pattern.clone();
+                self.restore_view(origin)?;
+            }
+        }
+        self.draw()?;
+        Ok(true)
+    }
+
+    fn restore_view(&mut self, state: &ViewState) -> Result<()> {
+        self.start_line = state.start_line;
+        self.end_line = state.end_line;
+        self.cursor = state.cursor;
+        self.log_viewer.set_cursor(self.cursor.0, self.cursor.1)
+    }
+
+    /// Tab at the prompt: complete the word before the cursor. With several
+    /// candidates the shared prefix is inserted and they are listed on the
+    /// status row; more Tabs (Shift-Tab backwards) cycle through them.
+    fn complete(
+        &mut self,
+        editor: &mut LineEditor,
+        completion: &mut Option<(Completion, Option<usize>)>,
+        backward: bool,
+    ) -> Result<()> {
+        if let Some((found, selected)) = completion {
+            let count = found.candidates.len();
+            let next = match (*selected, backward) {
+                (None, false) => 0,
+                (None, true) => count - 1,
+                (Some(i), false) => (i + 1) % count,
+                (Some(i), true) => (i + count - 1) % count,
+            };
+            *selected = Some(next);
+            editor.replace_before_cursor(found.start, &found.candidates[next]);
+            return self.log_viewer.print_completions(&found.candidates, *selected);
+        }
+
+        let context = completion::Context::new(&self.log_file, &self.formats);
+        let found = completion::complete(&editor.before_cursor(), &context);
+        match found.candidates.as_slice() {
+            [] => {}
+            [candidate] => {
+                // Directories and field patterns go on, anything else is a whole word
+                let end = if candidate.ends_with(['/', '=']) { "" } else { " " };
+                editor.replace_before_cursor(found.start, &format!("{}{}", candidate, end));
+            }
+            candidates => {
+                editor.replace_before_cursor(found.start, &found.common_prefix());
+                self.log_viewer.print_completions(candidates, None)?;
+                *completion = Some((found, None));
+            }
+        }
+        Ok(())
+    }
+
+    fn history(&self, search: bool) -> &History {
+        if search {
+            &self.search_history
+        } else {
+            &self.command_history
+        }
+    }
+
+    /// Remember a line entered at the prompt; searches are kept without the `/` or `?`
+    fn add_to_history(&mut self, input: &str) {
+        let input = input.trim();
+        let result = match input.strip_prefix(['/', '?']) {
+            Some(pattern) => self.search_history.add(pattern),
+            None => self.command_history.add(input),
+        };
+        if let Err(e) = result {
+            debug!("Failed to save history: {:?}", e);
+        }
+    }
+
+    /// Ctrl-R at the prompt: find a history entry containing what is typed,
+    /// Ctrl-R again for an older one. `None` when cancelled with Esc.
+    fn reverse_search(&mut self, search: bool) -> Result<Option<String>> {
+        let mut query = String::new();
+        let mut found: Option<usize> = None;
+
+        loop {
+            let history = self.history(search);
+            let label = if found.is_none() && !query.is_empty() {
+                "failing reverse-i-search"
+            } else {
+                "reverse-i-search"
+            };
+            let entry = found.and_then(|i| history.get(i)).unwrap_or_default();
+            let mut prompt = LineEditor::new(&format!("({})`{}': {}", label, query, entry));
+            self.log_viewer.print_prompt(&mut prompt)?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            let history = self.history(search);
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Enter => return Ok(found.and_then(|i| history.get(i)).map(String::from)),
+                KeyCode::Esc => return Ok(None),
+                KeyCode::Char('r') if ctrl => {
+                    found = history
+                        .search(&query, found.unwrap_or(history.len()))
+                        .or(found);
+                }
+                KeyCode::Char(c) if !ctrl => {
+                    query.push(c);
+                    found = history.search(&query, history.len());
+                }
+                KeyCode::Backspace => {
+                    query.pop();
+                    found = history.search(&query, history.len());
+                }
+                _ => {}
+            }
+        }
+    }
+
     fn handle_key_event(&mut self, key: KeyEvent) -> Result<bool> {
-        let mut redraw = true;
+        self.pending_keys.push(Key::from(key));
 
-        let key_char = match key.code {
-            KeyCode::Char(c) => Some(c),
-            _ => None, // Not a printable char
+        let binding = match self.keymap.lookup(&self.pending_keys) {
+            Lookup::Found(binding) => binding,
+            Lookup::Prefix => return Ok(false),
+            Lookup::None => {
+                // An unbound sequence starts over from its last key
+                let retry = self.pending_keys.len() > 1;
+                self.pending_keys.clear();
+                return if retry { self.handle_key_event(key) } else { Ok(false) };
+            }
         };
+        self.pending_keys.clear();
+
+        match binding {
+            Binding::Action(action) => self.run_key_action(action),
+            Binding::Command(command) => {
+                let action = handle_command(
+                    &command,
+                    self.get_current_line_number(),
+                    &mut self.log_file,
+                    &mut self.log_viewer,
+                    &self.formats,
+                )?;
+                self.handle_action(action)?;
+                self.refresh_timeline()?;
+                Ok(true)
+            }
+        }
+    }
 
-        match key.code {
-            KeyCode::Esc | KeyCode::Char('/') | KeyCode::Char('?') => {
-                
-                if let Some(val) = self.command_mode(key_char)? {
-                    debug!("Command mode returned with value: {}", val);
-
-                    self.start_line = val;
-                    self.end_line =
-                        (self.start_line + self.rows).min(self.get_active_log_file().total_lines());
-                    self.cursor = (0, 0);
-                    self.log_viewer.set_cursor(self.cursor.0, self.cursor.0)?;
-                } else {
-                    debug!("Exiting command mode");
-                    self.log_viewer.set_cursor(self.cursor.0, self.cursor.1)?;
+    /// Run a key action, returning whether the screen needs a redraw
+    fn run_key_action(&mut self, action: Action) -> Result<bool> {
+        let mut redraw = true;
+
+        match action {
+            Action::CommandPrompt | Action::SearchForward | Action::SearchBackward => {
+                let prefix = match action {
+                    Action::SearchForward => Some('/'),
+                    Action::SearchBackward => Some('?'),
+                    _ => None,
+                };
+                let action = self.command_mode(prefix)?;
+                self.handle_action(action)?;
+                // Filters may have changed, in the command or in an overlay it opened
+                self.refresh_timeline()?;
+            }
+            Action::SearchNext | Action::SearchPrev => {
+                let found = command_handler::search_next(
+                    self.get_current_line_number(),
+                    &self.log_file,
+                    &mut self.log_viewer,
+                    action == Action::SearchPrev,
+                );
+                match found {
+                    Some(line) => self.jump_to_line(line)?,
+                    None => redraw = false,
                 }
             }
-            KeyCode::Char('q') => {
+            Action::Quit => {
                 self.log_viewer.clear()?;
                 self.log_viewer.set_cursor(0, 0)?;
                 self.running = false; // Exit on 'q'
             }
-            KeyCode::Char('j') | KeyCode::Down => {
+            Action::LineDown => {
                 redraw = self.move_cursor(0, 1)?; // Move cursor down
             }
-            KeyCode::Char('k') | KeyCode::Up => {
+            Action::LineUp => {
                 redraw = self.move_cursor(0, -1)?; // Move cursor up
             }
-            KeyCode::Char('h') | KeyCode::Left => {
+            Action::Left => {
                 redraw = self.move_cursor(-1, 0)?; // Move cursor left
             }
-            KeyCode::Char('l') | KeyCode::Right => {
+            Action::Right => {
                 redraw = self.move_cursor(1, 0)?; // Move cursor right
             }
-            KeyCode::Char(' ') if key.modifiers.contains(event::KeyModifiers::CONTROL) => {
+            Action::PageUp => {
                 self.page_up();
             }
-            KeyCode::Char('b') | KeyCode::PageUp => {
-                self.page_up();
-            }
-            KeyCode::Char(' ') | KeyCode::Char('f') | KeyCode::PageDown => {
+            Action::PageDown => {
                 self.page_down();
             }
-            KeyCode::Char('e') => {
+            Action::ToggleExpand => {
                 match self.mode {
                     ViewMode::Normal => self.switch_to_expanded_mode()?,
                     ViewMode::Expanded => self.switch_to_normal_mode(),
@@ -273,27 +830,575 @@ impl Controller {
                 self.cursor = (0, 0);
                 self.log_viewer.set_cursor(0, 0)?;
             }
-            KeyCode::Char('g') | KeyCode::Char('<') => {
+            Action::Top => {
                 // Go to the first line
                 self.start_line = 0;
                 self.end_line = self.rows;
                 self.cursor = (0, 0);
                 self.log_viewer.set_cursor(0, 0)?;
             }
-            KeyCode::Char('G') | KeyCode::Char('>')=> {
+            Action::Bottom => {
                 // Go to the last line
-                (self.start_line, self.end_line) = self.log_file.get_end_of_file(self.rows, self.cols, 3);
+                (self.start_line, self.end_line) = self.page_ending_at(self.log_file.total_lines());
                 self.cursor = (0, 0);
                 self.log_viewer.set_cursor(0, 0)?;
             }
-            KeyCode::Char('x') => {
-                self.log_file.hide_line(self.get_current_line_number());
+            Action::NextError | Action::PrevError => {
+                redraw = self.jump_to_error(action == Action::NextError)?;
+                if !redraw {
+                    self.log_viewer.set_message(Message::warn("No more errors"));
+                }
+            }
+            Action::NextAnomaly | Action::PrevAnomaly => {
+                redraw = self.jump_to_anomaly(action == Action::NextAnomaly)?;
+                if !redraw {
+                    self.log_viewer.set_message(Message::warn("No more anomalies"));
+                }
+            }
+            Action::Unfold => {
+                // Expand the folded run under the cursor
+                redraw = self.log_file.unfold(self.get_current_line_number());
+                self.refresh_timeline()?;
+            }
+            Action::CycleTimeline => {
+                // Cycle the timeline panel: off, plain, stacked by level
+                let stacked = match &self.timeline {
+                    None => Some(false),
+                    Some(timeline) if !timeline.stacked => Some(true),
+                    Some(_) => None,
+                };
+                self.set_timeline(stacked)?;
+            }
+            Action::TimelinePrev | Action::TimelineNext => {
+                let delta = if action == Action::TimelinePrev { -1 } else { 1 };
+                let line = self.timeline.as_mut().and_then(|t| t.move_selection(delta));
+                match line {
+                    Some(line) => self.jump_to_line(line)?,
+                    None => redraw = false,
+                }
+            }
+            Action::ToggleTable => {
+                self.log_viewer.toggle_table_view();
+            }
+            Action::SetMark if matches!(self.mode, ViewMode::Normal) => {
+                // Mark the line under the cursor
+                if let Some(name) = read_mark_name()? {
+                    self.marks.insert(name, self.get_current_line_number());
+                }
+            }
+            Action::JumpToMark if matches!(self.mode, ViewMode::Normal) => {
+                // Jump to a mark
+                let Some(name) = read_mark_name()? else {
+                    return Ok(false);
+                };
+                match self.marks.get(&name).copied() {
+                    Some(line) => self.jump_to_line(line)?,
+                    None => self.log_viewer.set_message(Message::warn(format!("Mark '{}' not set", name))),
+                }
+            }
+            Action::SetMark | Action::JumpToMark => redraw = false,
+            Action::HideLine => {
+                self.log_file.hide_record(self.get_current_line_number())?;
+                self.refresh_timeline()?;
             }
-            _ => {}
         }
         Ok(redraw)
     }
 
+    /// Carry out what a command asked for
+    fn handle_action(&mut self, action: Option<CommandAction>) -> Result<()> {
+        match action {
+            Some(CommandAction::Jump(val)) => {
+                debug!("Command mode returned with value: {}", val);
+                self.jump_to_line(val)?;
+            }
+            Some(CommandAction::ShowPatterns) => {
+                self.show_patterns()?;
+            }
+            Some(CommandAction::ShowStats(field)) => {
+                self.show_stats(field.as_deref())?;
+            }
+            Some(CommandAction::Extract(pattern, source)) => {
+                self.show_extract(&pattern, &source)?;
+            }
+            Some(CommandAction::Query(query)) => {
+                self.show_query(&query)?;
+            }
+            Some(CommandAction::ShowMarks) => {
+                self.show_marks()?;
+            }
+            Some(CommandAction::Note(text)) if matches!(self.mode, ViewMode::Normal) => {
+                let line = self.get_current_line_number();
+                self.notes.set(&self.log_file, line, &text)?;
+                let done = if text.is_empty() { "Note removed" } else { "Note added" };
+                self.log_viewer.set_message(Message::info(done));
+            }
+            Some(CommandAction::Note(_)) => {
+                self.log_viewer.set_message(Message::warn("Notes can only be added in the normal view"));
+            }
+            Some(CommandAction::ShowNotes) => {
+                self.show_notes()?;
+            }
+            Some(CommandAction::ExportNotes(path)) => {
+                self.notes.export_markdown(&self.log_file, &self.file_name, &path)?;
+                self.log_viewer.set_message(Message::info(format!("Wrote notes to {}", path)));
+            }
+            Some(CommandAction::SaveSession(name)) => {
+                self.save_session(Some(&name))?;
+                self.log_viewer.set_message(Message::info(format!("Saved session {}", name)));
+            }
+            Some(CommandAction::LoadSession(name)) => {
+                self.load_session(&name)?;
+                self.log_viewer.set_message(Message::info(format!("Loaded session {}", name)));
+            }
+            Some(CommandAction::Map { keys, target }) => {
+                self.keymap.map(&keys, &target)?;
+                self.log_viewer.set_message(Message::info(format!("Mapped {} to {}", keys, target)));
+            }
+            Some(CommandAction::Diff(path)) => {
+                self.show_diff(&path)?;
+            }
+            Some(CommandAction::SetAnomalies(rarity)) => {
+                self.anomalies = rarity.map(|rarity| {
+                    template::rare_records(&template::mine(&self.log_file), rarity)
+                });
+                let text = match &self.anomalies {
+                    Some(records) => format!("Marked {} rare records", log_viewer::format_count(records.len())),
+                    None => "Anomaly markers off".to_string(),
+                };
+                self.log_viewer.set_message(Message::info(text));
+            }
+            None => {
+                debug!("Exiting command mode");
+                self.log_viewer.set_cursor(self.cursor.0, self.cursor.1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Scroll so that `line` is at the top of the screen
+    fn jump_to_line(&mut self, line: usize) -> Result<()> {
+        self.start_line = line;
+        self.end_line = (self.start_line + self.rows).min(self.get_active_log_file().total_lines());
+        self.cursor = (0, 0);
+        self.log_viewer.set_cursor(self.cursor.0, self.cursor.1)?;
+        Ok(())
+    }
+
+    /// Show the timeline panel (stacked by level or not), or hide it with `None`
+    fn set_timeline(&mut self, stacked: Option<bool>) -> Result<()> {
+        let (_, cols) = self.log_viewer.get_row_cols()?;
+        self.timeline = stacked.and_then(|stacked| Timeline::build(&self.log_file, cols, stacked));
+        if let Some(timeline) = &mut self.timeline {
+            timeline.select_line(self.start_line);
+        }
+
+        self.log_viewer.set_panel_rows(if self.timeline.is_some() {
+            log_viewer::TIMELINE_ROWS
+        } else {
+            0
+        });
+        let (rows, cols) = self.log_viewer.get_row_cols()?;
+        self.rows = rows;
+        self.cols = cols;
+        self.log_viewer.set_cursor(self.cursor.0, self.cursor.1)?;
+        Ok(())
+    }
+
+    /// Rebuild the timeline after the visible lines changed; search matches
+    /// are counted again on the next draw
+    fn refresh_timeline(&mut self) -> Result<()> {
+        self.search_matches = None;
+        if let Some(timeline) = &self.timeline {
+            let (stacked, selected) = (timeline.stacked, timeline.selected);
+            self.set_timeline(Some(stacked))?;
+            if let Some(timeline) = &mut self.timeline {
+                timeline.selected = selected.min(timeline.buckets.len().saturating_sub(1));
+            }
+        }
+        Ok(())
+    }
+
+    /// Jump to the next (or previous) visible record logged at error level or
+    /// above. Returns false if there is none.
+    fn jump_to_error(&mut self, forward: bool) -> Result<bool> {
+        let Some(format) = self.log_file.format() else {
+            return Ok(false);
+        };
+
+        let current = self.log_file.record_start(self.get_current_line_number());
+        let is_error = |start: usize| {
+            self.log_file.is_line_visible(start)
+                && self
+                    .log_file
+                    .get_line(start)
+                    .and_then(|line| format.parse(line))
+                    .is_some_and(|record| record.level >= Some(Level::Error))
+        };
+
+        let mut target = None;
+        if forward {
+            let mut start = self.log_file.record_end(current);
+            while start < self.log_file.total_lines() {
+                if is_error(start) {
+                    target = Some(start);
+                    break;
+                }
+                start = self.log_file.record_end(start);
+            }
+        } else {
+            let mut line = current;
+            while line > 0 {
+                let start = self.log_file.record_start(line - 1);
+                if is_error(start) {
+                    target = Some(start);
+                    break;
+                }
+                line = start;
+            }
+        }
+
+        match target {
+            Some(line) => {
+                self.jump_to_line(line)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Jump to the next (or previous) visible anomaly. Returns false if there is none.
+    fn jump_to_anomaly(&mut self, forward: bool) -> Result<bool> {
+        let Some(anomalies) = &self.anomalies else {
+            return Ok(false);
+        };
+
+        let current = self.get_current_line_number();
+        let log_file = &self.log_file;
+        let target = if forward {
+            anomalies
+                .range(current + 1..)
+                .find(|&&line| log_file.is_line_visible(line))
+        } else {
+            anomalies
+                .range(..current)
+                .rev()
+                .find(|&&line| log_file.is_line_visible(line))
+        };
+
+        match target.copied() {
+            Some(line) => {
+                self.jump_to_line(line)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Compare the visible lines with another file side by side, aligned on
+    /// their normalized messages. `]`/`[` jump between blocks of differences.
+    fn show_diff(&mut self, path: &str) -> Result<()> {
+        let mut other = log_file::LogFile::new(path)?;
+        other.detect_format(&self.formats);
+
+        let rows = diff::diff_files(&self.log_file, &other);
+        let changes = diff::change_starts(&rows);
+        let mut top = 0;
+
+        loop {
+            let height = self.rows;
+            let last_top = rows.len().saturating_sub(height);
+            self.log_viewer.print_diff(&rows, top, &self.log_file, &other)?;
+            self.log_viewer.print_status(&format!(
+                "{} <> {}  {} differences  ]/[: next/prev  q: close",
+                self.file_name,
+                path,
+                changes.len()
+            ))?;
+
+            if let Event::Key(KeyEvent { code, .. }) = event::read()? {
+                match code {
+                    KeyCode::Char('j') | KeyCode::Down => top = (top + 1).min(last_top),
+                    KeyCode::Char('k') | KeyCode::Up => top = top.saturating_sub(1),
+                    KeyCode::Char(' ') | KeyCode::Char('f') | KeyCode::PageDown => {
+                        top = (top + height).min(last_top)
+                    }
+                    KeyCode::Char('b') | KeyCode::PageUp => top = top.saturating_sub(height),
+                    KeyCode::Char('g') => top = 0,
+                    KeyCode::Char('G') => top = last_top,
+                    KeyCode::Char(']') => {
+                        if let Some(&next) = changes.iter().find(|&&c| c > top) {
+                            top = next;
+                        }
+                    }
+                    KeyCode::Char('[') => {
+                        if let Some(&prev) = changes.iter().rev().find(|&&c| c < top) {
+                            top = prev;
+                        }
+                    }
+                    KeyCode::Char('q') | KeyCode::Esc => break,
+                    _ => {}
+                }
+            }
+        }
+
+        self.log_viewer.set_cursor(self.cursor.0, self.cursor.1)?;
+        Ok(())
+    }
+
+    /// Draw an overlay and handle its navigation keys until another key is
+    /// pressed. Returns that key, or None when the overlay is closed with q/Esc.
+    fn browse_overlay(&mut self, overlay: &mut ListOverlay) -> Result<Option<KeyCode>> {
+        loop {
+            self.log_viewer.print_overlay(overlay)?;
+            let height = self.log_viewer.overlay_height()?;
+
+            if let Event::Key(KeyEvent { code, .. }) = event::read()? {
+                match code {
+                    KeyCode::Char('j') | KeyCode::Down => overlay.move_selection(1, height),
+                    KeyCode::Char('k') | KeyCode::Up => overlay.move_selection(-1, height),
+                    KeyCode::PageDown | KeyCode::Char(' ') => {
+                        overlay.move_selection(height as isize, height)
+                    }
+                    KeyCode::PageUp => overlay.move_selection(-(height as isize), height),
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+                    other => return Ok(Some(other)),
+                }
+            }
+        }
+    }
+
+    /// Show the summary statistics of the visible lines; `w` saves them next to the log
+    fn show_stats(&mut self, field: Option<&str>) -> Result<()> {
+        let report = stats::summarize(&self.log_file, field);
+        let export_path = self.export_path("stats.txt");
+
+        let mut overlay = ListOverlay::new(
+            &format!("Statistics: {}", self.file_name),
+            "",
+            report.clone(),
+        );
+        overlay.footer = format!("j/k: scroll  w: save to {}  q: close", export_path);
+
+        while let Some(code) = self.browse_overlay(&mut overlay)? {
+            if code == KeyCode::Char('w') {
+                stats::export(&report, &export_path)?;
+                overlay.footer = format!("Saved to {}  q: close", export_path);
+            }
+        }
+
+        self.log_viewer.set_cursor(self.cursor.0, self.cursor.1)?;
+        Ok(())
+    }
+
+    /// Show the distribution of a numeric series; `w` exports it as CSV next to the log
+    fn show_extract(&mut self, pattern: &str, source: &extract::Source) -> Result<()> {
+        let points = extract::extract(&self.log_file, source);
+        let export_path = self.export_path("extract.csv");
+        let (_, cols) = self.log_viewer.get_row_cols()?;
+
+        let rows = match extract::Summary::of(&points) {
+            Some(summary) => {
+                let mut rows = summary.rows();
+                rows.push(String::new());
+                rows.push(extract::sparkline(&points, cols));
+                rows
+            }
+            None => vec!["No values found".to_string()],
+        };
+
+        let mut overlay = ListOverlay::new(&format!("Extract: {}", pattern), "", rows);
+        overlay.footer = format!("w: save to {}  q: close", export_path);
+
+        while let Some(code) = self.browse_overlay(&mut overlay)? {
+            if code == KeyCode::Char('w') {
+                extract::export_csv(&points, &export_path)?;
+                overlay.footer = format!("Saved {} values to {}  q: close", points.len(), export_path);
+            }
+        }
+
+        self.log_viewer.set_cursor(self.cursor.0, self.cursor.1)?;
+        Ok(())
+    }
+
+    /// Where an export is saved by default: next to the log, named after it
+    fn export_path(&self, extension: &str) -> String {
+        let name = format!("{}.{}", self.file_name, extension);
+        self.log_path.with_file_name(name).to_string_lossy().into_owned()
+    }
+
+    /// Show the result table of a query. The records behind the selected row
+    /// can be turned into a show (`s`) or hide (`h`) filter.
+    fn show_query(&mut self, query: &str) -> Result<()> {
+        let result = Query::parse(query)?.run(&self.log_file);
+        let (header, rows) = result.table();
+
+        let mut overlay = ListOverlay::new(
+            &format!("Query: {}  ({} rows)", query, result.rows.len()),
+            &header,
+            rows,
+        );
+        overlay.footer = "j/k: move  s/Enter: show only  h: hide  q: close".to_string();
+
+        while let Some(code) = self.browse_overlay(&mut overlay)? {
+            match code {
+                KeyCode::Char('s') | KeyCode::Enter => {
+                    if let Some(row) = result.rows.get(overlay.selected) {
+                        self.log_file.apply_filter(FilterRule::ShowQuery {
+                            query: query.to_string(),
+                            key: row.key.clone(),
+                        })?;
+                    }
+                    break;
+                }
+                KeyCode::Char('h') => {
+                    if let Some(row) = result.rows.get(overlay.selected) {
+                        self.log_file.apply_filter(FilterRule::HideQuery {
+                            query: query.to_string(),
+                            key: row.key.clone(),
+                        })?;
+                    }
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        self.cursor = (0, 0);
+        self.log_viewer.set_cursor(0, 0)?;
+        Ok(())
+    }
+
+    /// List the marks; Enter jumps to the selected one, `d` deletes it
+    fn show_marks(&mut self) -> Result<()> {
+        let mut overlay = ListOverlay::new("Marks", "mark      line  text", Vec::new());
+        overlay.footer = "j/k: move  Enter: jump  d: delete  q: close".to_string();
+
+        loop {
+            let marks: Vec<(char, usize)> = self.marks.iter().map(|(k, v)| (*k, *v)).collect();
+            overlay.rows = marks
+                .iter()
+                .map(|(name, line)| {
+                    let text = self.log_file.get_line(*line).unwrap_or("");
+                    format!("{:<4} {:>9}  {}", name, line + 1, text)
+                })
+                .collect();
+            overlay.selected = overlay.selected.min(marks.len().saturating_sub(1));
+
+            match self.browse_overlay(&mut overlay)? {
+                Some(KeyCode::Enter) => {
+                    if let Some((_, line)) = marks.get(overlay.selected) {
+                        self.jump_to_line(*line)?;
+                        return Ok(());
+                    }
+                }
+                Some(KeyCode::Char('d')) => {
+                    if let Some((name, _)) = marks.get(overlay.selected) {
+                        self.marks.remove(name);
+                    }
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+
+        self.log_viewer.set_cursor(self.cursor.0, self.cursor.1)?;
+        Ok(())
+    }
+
+    /// List the notes; Enter jumps to the selected line, `w` exports them as markdown
+    fn show_notes(&mut self) -> Result<()> {
+        let lines: Vec<usize> = self.notes.lines(&self.log_file).iter().map(|(line, _)| *line).collect();
+        let rows = self
+            .notes
+            .lines(&self.log_file)
+            .iter()
+            .map(|(line, text)| format!("{:>9}  {}", line + 1, text))
+            .collect();
+        let export_path = self.export_path("notes.md");
+
+        let mut overlay = ListOverlay::new("Notes", "     line  note", rows);
+        overlay.footer = format!("j/k: move  Enter: jump  w: save to {}  q: close", export_path);
+
+        while let Some(code) = self.browse_overlay(&mut overlay)? {
+            match code {
+                KeyCode::Enter => {
+                    if let Some(line) = lines.get(overlay.selected) {
+                        self.jump_to_line(*line)?;
+                        return Ok(());
+                    }
+                }
+                KeyCode::Char('w') => {
+                    self.notes.export_markdown(&self.log_file, &self.file_name, &export_path)?;
+                    overlay.footer = format!("Saved to {}  q: close", export_path);
+                }
+                _ => {}
+            }
+        }
+
+        self.log_viewer.set_cursor(self.cursor.0, self.cursor.1)?;
+        Ok(())
+    }
+
+    /// Show the message templates of the visible lines. The selected template
+    /// can be turned into a show (`s`) or hide (`h`) filter.
+    fn show_patterns(&mut self) -> Result<()> {
+        let mut templates = template::mine(&self.log_file);
+        let mut sort = PatternSort::Count;
+        sort.sort(&mut templates);
+
+        let pattern_rows = |templates: &[Template]| -> Vec<String> {
+            templates
+                .iter()
+                .map(|t| {
+                    format!(
+                        "{:>9} {:>9} {:>9}  {}",
+                        log_viewer::format_count(t.count()),
+                        t.first() + 1,
+                        t.last() + 1,
+                        t.text()
+                    )
+                })
+                .collect()
+        };
+
+        let mut overlay = ListOverlay::new(
+            &format!("Patterns ({})", templates.len()),
+            &format!("{:>9} {:>9} {:>9}  {}", "Count", "First", "Last", "Template"),
+            pattern_rows(&templates),
+        );
+        overlay.footer = "j/k: move  o: sort  s/Enter: show only  h: hide  q: close".to_string();
+
+        while let Some(code) = self.browse_overlay(&mut overlay)? {
+            match code {
+                KeyCode::Char('o') => {
+                    sort = sort.next();
+                    sort.sort(&mut templates);
+                    overlay.rows = pattern_rows(&templates);
+                    overlay.selected = 0;
+                    overlay.scroll = 0;
+                }
+                KeyCode::Char('s') | KeyCode::Enter => {
+                    if let Some(t) = templates.get(overlay.selected) {
+                        self.log_file.apply_filter(FilterRule::ShowTemplate(t.text()))?;
+                    }
+                    break;
+                }
+                KeyCode::Char('h') => {
+                    if let Some(t) = templates.get(overlay.selected) {
+                        self.log_file.apply_filter(FilterRule::HideTemplate(t.text()))?;
+                    }
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        self.cursor = (0, 0);
+        self.log_viewer.set_cursor(0, 0)?;
+        Ok(())
+    }
+
     fn move_cursor(&mut self, x: i16, y: i16) -> Result<bool> {
         let new_x = self.cursor.0 as i16 + x;
         let new_y = self.cursor.1 as i16 + y;
@@ -340,7 +1445,17 @@ impl Controller {
 
     fn page_up(&mut self) {
         debug!("Page up called");
-	(self.start_line, self.end_line) = self.log_file.get_pos_from_end_line(self.start_line, self.rows, self.cols, 3);
+	(self.start_line, self.end_line) = self.page_ending_at(self.start_line);
+    }
+
+    /// First and last line of the page ending just before `end_pos`, with
+    /// lines as tall as the current view draws them
+    fn page_ending_at(&self, end_pos: usize) -> (usize, usize) {
+        let (lf, viewer) = (&self.log_file, &self.log_viewer);
+        lf.get_pos_from_end_line(end_pos, self.rows, |i| {
+            let data = lf.get_line(i).unwrap_or_default();
+            viewer.line_rows(data, lf.folded_count(i), lf.format(), self.cols)
+        })
     }
 
     fn page_down(&mut self) {
@@ -369,6 +1484,79 @@ impl Controller {
         Ok(())
     }
 
+    /// Whether search matches are left to count
+    fn counting_matches(&self) -> bool {
+        match (&self.log_viewer.search_pattern, &self.search_matches) {
+            (None, _) => false,
+            (Some(pattern), Some(count)) if *pattern == count.pattern => {
+                count.next < self.log_file.total_lines()
+            }
+            _ => true,
+        }
+    }
+
+    /// Count search matches over the next chunk of lines, starting over when
+    /// the pattern changed
+    fn count_matches(&mut self) {
+        let Some(pattern) = &self.log_viewer.search_pattern else {
+            self.search_matches = None;
+            return;
+        };
+        if self.search_matches.as_ref().is_none_or(|count| count.pattern != *pattern) {
+            self.search_matches = Some(MatchCount {
+                pattern: pattern.clone(),
+                matches: Vec::new(),
+                next: 0,
+            });
+        }
+
+        if let Some(count) = &mut self.search_matches {
+            let (matches, next) = self.log_file.find_matches(&count.pattern, count.next, COUNT_CHUNK_LINES);
+            count.matches.extend(matches);
+            count.next = next;
+        }
+    }
+
+    fn draw_status(&mut self) -> Result<()> {
+        let cursor_line = self.line_numbers.get(self.cursor.1 as usize).copied();
+        // Lines of the expanded view are not lines of the file
+        let file_line = cursor_line.filter(|_| matches!(self.mode, ViewMode::Normal));
+        let active_log_file = self.get_active_log_file();
+        let log_file = &self.log_file;
+
+        // Only a count of the current pattern is worth showing
+        let search = match (&self.log_viewer.search_pattern, &self.search_matches) {
+            (Some(pattern), Some(count)) if *pattern == count.pattern => search_status(
+                pattern,
+                &count.matches,
+                count.next >= log_file.total_lines(),
+                file_line.map(|line| log_file.record_start(line)),
+            ),
+            (Some(pattern), _) => format!("/{}", pattern),
+            (None, _) => String::new(),
+        };
+        let status = StatusInfo {
+            file: self.file_name.clone(),
+            format: log_file.format().map_or("plain", |format| format.name()).to_string(),
+            line: cursor_line.map_or(0, |line| line + 1),
+            total: active_log_file.total_lines(),
+            visible: active_log_file.visible_lines(),
+            filters: log_file.filters().len(),
+            search,
+            mode: match self.mode {
+                ViewMode::Normal => "Normal",
+                ViewMode::Expanded => "Expanded",
+            }
+            .to_string(),
+            note: file_line
+                .and_then(|line| self.notes.get(log_file, line))
+                .map(|note| format!("note: {}", note))
+                .unwrap_or_default(),
+        };
+        let status = status::render(&self.status_format, &status);
+        self.log_viewer.print_status(&status)
+    }
+
     fn draw(&mut self) -> Result<()> {
         // Destructure self so that we can borrow log_viewer and log_file independently.
         let Controller {
@@ -381,6 +1569,10 @@ impl Controller {
             line_numbers,
             expanded_log_file,
             mode,
+            anomalies,
+            timeline,
+            marks,
+            notes,
             .. // Ignore other fields for now
         } = self;
 
@@ -393,9 +1585,21 @@ impl Controller {
 
         debug!("Drawing lines from {} rows {}", *start_line, *rows);
         let visible_lines = active_log_file.get_visible_lines(*start_line, *rows);
-        *line_numbers = log_viewer.print_screen(&visible_lines)?;
+        log_viewer.set_gutter(match mode {
+            ViewMode::Normal => {
+                let noted: BTreeSet<usize> =
+                    notes.lines(log_file).into_iter().map(|(line, _)| line).collect();
+                gutter_markers(anomalies, marks, &noted, &visible_lines)
+            }
+            ViewMode::Expanded => None,
+        });
+        *line_numbers = log_viewer.print_screen(&visible_lines, active_log_file.format())?;
         debug!("Line numbers: {:?}", line_numbers);
 
+        if let (Some(timeline), ViewMode::Normal) = (timeline.as_ref(), &*mode) {
+            log_viewer.print_timeline(timeline)?;
+        }
+
         *start_line = line_numbers.first().cloned().unwrap_or(0);
         *end_line = line_numbers.last().cloned().unwrap_or(0);
 
@@ -406,6 +1610,56 @@ impl Controller {
         }
 
         debug!("Drawing lines from {} to {}", *start_line,*end_line);
-        Ok(())
+        self.draw_status()?;
+        self.log_viewer.print_message()
+    }
+}
+
+/// The search pattern and where `record` is among its matches; a count still
+/// going on is shown with a `+`
+fn search_status(pattern: &str, matches: &[usize], done: bool, record: Option<usize>) -> String {
+    let total = format!("{}{}", log_viewer::format_count(matches.len()), if done { "" } else { "+" });
+    match record.map(|record| matches.binary_search(&record)) {
+        _ if done && matches.is_empty() => format!("/{}  no matches", pattern),
+        Some(Ok(index)) => format!("/{}  match {} of {}", pattern, index + 1, total),
+        _ => format!("/{}  {} matches", pattern, total),
+    }
+}
+
+/// Gutter markers for the lines on screen, or None when no marker feature is active.
+/// Marks take precedence over notes, notes over anomalies.
+fn gutter_markers(
+    anomalies: &Option<BTreeSet<usize>>,
+    marks: &BTreeMap<char, usize>,
+    noted: &BTreeSet<usize>,
+    lines: &[Line],
+) -> Option<HashMap<usize, GutterMarker>> {
+    if anomalies.is_none() && marks.is_empty() && noted.is_empty() {
+        return None;
+    }
+
+    let mut markers = HashMap::new();
+    for line in lines {
+        if noted.contains(&line.line_number) {
+            markers.insert(line.line_number, ('*', Color::Yellow));
+        } else if anomalies.as_ref().is_some_and(|a| a.contains(&line.line_number)) {
+            markers.insert(line.line_number, ('!', Color::Magenta));
+        }
+    }
+    for (name, line) in marks {
+        markers.insert(*line, (*name, Color::Cyan));
+    }
+    Some(markers)
+}
+
+/// Wait for the name of a mark; anything but a-z cancels
+fn read_mark_name() -> Result<Option<char>> {
+    loop {
+        if let Event::Key(KeyEvent { code, .. }) = event::read()? {
+            return Ok(match code {
+                KeyCode::Char(c) if c.is_ascii_lowercase() => Some(c),
+                _ => None,
+            });
+        }
     }
 }
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use crate::log_file::LogFile;
+use crate::template;
+
+/// Regions without unique common lines are aligned with a quadratic LCS only
+/// up to this many cells; larger ones are shown as removed then added.
+const LCS_LIMIT: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffKind {
+    Equal,
+    /// Only in the left file
+    Removed,
+    /// Only in the right file
+    Added,
+}
+
+/// One aligned row of the side-by-side view, holding line numbers of each file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiffRow {
+    pub left: Option<usize>,
+    pub right: Option<usize>,
+    pub kind: DiffKind,
+}
+
+/// Align the visible lines of two files, comparing normalized messages
+pub fn diff_files(left: &LogFile, right: &LogFile) -> Vec<DiffRow> {
+    let (left_lines, left_keys) = keys(left);
+    let (right_lines, right_keys) = keys(right);
+
+    diff(&left_keys, &right_keys)
+        .into_iter()
+        .map(|(l, r, kind)| DiffRow {
+            left: l.map(|i| left_lines[i]),
+            right: r.map(|i| right_lines[i]),
+            kind,
+        })
+        .collect()
+}
+
+fn keys(lf: &LogFile) -> (Vec<usize>, Vec<u64>) {
+    let mut lines = Vec::new();
+    let mut keys = Vec::new();
+    for i in 0..lf.total_lines() {
+        if !lf.is_line_visible(i) {
+            continue;
+        }
+        if let Some(line) = lf.get_line(i) {
+            let mut hasher = DefaultHasher::new();
+            template::normalized_message(lf, line).hash(&mut hasher);
+            lines.push(i);
+            keys.push(hasher.finish());
+        }
+    }
+    (lines, keys)
+}
+
+type Alignment = Vec<(Option<usize>, Option<usize>, DiffKind)>;
+
+/// Patience diff: lines unique to both sides anchor the alignment, the gaps
+/// between anchors are diffed recursively, falling back to LCS.
+pub fn diff(a: &[u64], b: &[u64]) -> Alignment {
+    let mut out = Vec::new();
+    diff_range(a, b, 0, a.len(), 0, b.len(), &mut out);
+    out
+}
+
+fn diff_range(
+    a: &[u64],
+    b: &[u64],
+    mut a_lo: usize,
+    mut a_hi: usize,
+    mut b_lo: usize,
+    mut b_hi: usize,
+    out: &mut Alignment,
+) {
+    // Common prefix
+    while a_lo < a_hi && b_lo < b_hi && a[a_lo] == b[b_lo] {
+        out.push((Some(a_lo), Some(b_lo), DiffKind::Equal));
+        a_lo += 1;
+        b_lo += 1;
+    }
+
+    // Common suffix, emitted after the middle
+    let mut suffix = Vec::new();
+    while a_lo < a_hi && b_lo < b_hi && a[a_hi - 1] == b[b_hi - 1] {
+        a_hi -= 1;
+        b_hi -= 1;
+        suffix.push((Some(a_hi), Some(b_hi), DiffKind::Equal));
+    }
+
+    if a_lo == a_hi || b_lo == b_hi {
+        push_changes(a_lo, a_hi, b_lo, b_hi, out);
+    } else {
+        let anchors = unique_anchors(a, b, a_lo, a_hi, b_lo, b_hi);
+        if anchors.is_empty() {
+            lcs(a, b, a_lo, a_hi, b_lo, b_hi, out);
+        } else {
+            let (mut prev_a, mut prev_b) = (a_lo, b_lo);
+            for (ai, bi) in anchors {
+                diff_range(a, b, prev_a, ai, prev_b, bi, out);
+                out.push((Some(ai), Some(bi), DiffKind::Equal));
+                prev_a = ai + 1;
+                prev_b = bi + 1;
+            }
+            diff_range(a, b, prev_a, a_hi, prev_b, b_hi, out);
+        }
+    }
+
+    out.extend(suffix.into_iter().rev());
+}
+
+fn push_changes(a_lo: usize, a_hi: usize, b_lo: usize, b_hi: usize, out: &mut Alignment) {
+    out.extend((a_lo..a_hi).map(|i| (Some(i), None, DiffKind::Removed)));
+    out.extend((b_lo..b_hi).map(|j| (None, Some(j), DiffKind::Added)));
+}
+
+/// Lines occurring exactly once on each side, reduced to the longest
+/// sequence that is increasing on both sides
+fn unique_anchors(
+    a: &[u64],
+    b: &[u64],
+    a_lo: usize,
+    a_hi: usize,
+    b_lo: usize,
+    b_hi: usize,
+) -> Vec<(usize, usize)> {
+    let mut counts: HashMap<u64, (usize, usize, usize, usize)> = HashMap::new();
+    for (i, key) in a.iter().enumerate().take(a_hi).skip(a_lo) {
+        let entry = counts.entry(*key).or_default();
+        entry.0 += 1;
+        entry.2 = i;
+    }
+    for (j, key) in b.iter().enumerate().take(b_hi).skip(b_lo) {
+        let entry = counts.entry(*key).or_default();
+        entry.1 += 1;
+        entry.3 = j;
+    }
+
+    let mut pairs: Vec<(usize, usize)> = counts
+        .values()
+        .filter(|(in_a, in_b, _, _)| *in_a == 1 && *in_b == 1)
+        .map(|(_, _, i, j)| (*i, *j))
+        .collect();
+    pairs.sort();
+
+    longest_increasing(&pairs)
+}
+
+/// Longest subsequence of pairs (sorted by first) that is increasing in second
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut tails: Vec<usize> = Vec::new();
+    let mut prev: Vec<Option<usize>> = vec![None; pairs.len()];
+
+    for (k, &(_, j)) in pairs.iter().enumerate() {
+        let pos = tails.partition_point(|&t| pairs[t].1 < j);
+        if pos > 0 {
+            prev[k] = Some(tails[pos - 1]);
+        }
+        if pos == tails.len() {
+            tails.push(k);
+        } else {
+            tails[pos] = k;
+        }
+    }
+
+    let mut result = Vec::new();
+    let mut cur = tails.last().copied();
+    while let Some(k) = cur {
+        result.push(pairs[k]);
+        cur = prev[k];
+    }
+    result.reverse();
+    result
+}
+
+fn lcs(
+    a: &[u64],
+    b: &[u64],
+    a_lo: usize,
+    a_hi: usize,
+    b_lo: usize,
+    b_hi: usize,
+    out: &mut Alignment,
+) {
+    let n = a_hi - a_lo;
+    let m = b_hi - b_lo;
+    if n * m > LCS_LIMIT {
+        push_changes(a_lo, a_hi, b_lo, b_hi, out);
+        return;
+    }
+
+    // table[i][j] = LCS length of a[a_lo + i..a_hi] and b[b_lo + j..b_hi]
+    let mut table = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[i][j] = if a[a_lo + i] == b[b_lo + j] {
+                table[i + 1][j + 1] + 1
+            } else {
+                table[i + 1][j].max(table[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a[a_lo + i] == b[b_lo + j] {
+            out.push((Some(a_lo + i), Some(b_lo + j), DiffKind::Equal));
+            i += 1;
+            j += 1;
+        } else if table[i + 1][j] >= table[i][j + 1] {
+            out.push((Some(a_lo + i), None, DiffKind::Removed));
+            i += 1;
+        } else {
+            out.push((None, Some(b_lo + j), DiffKind::Added));
+            j += 1;
+        }
+    }
+    push_changes(a_lo + i, a_hi, b_lo + j, b_hi, out);
+}
+
+/// Index of the first row of each block of changes
+pub fn change_starts(rows: &[DiffRow]) -> Vec<usize> {
+    rows.iter()
+        .enumerate()
+        .filter(|(i, row)| {
+            row.kind != DiffKind::Equal && (*i == 0 || rows[i - 1].kind == DiffKind::Equal)
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(a: &[u64], b: &[u64]) -> String {
+        diff(a, b)
+            .iter()
+            .map(|(_, _, kind)| match kind {
+                DiffKind::Equal => '=',
+                DiffKind::Removed => '-',
+                DiffKind::Added => '+',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(kinds(&[1, 2, 3], &[1, 2, 3]), "===");
+        assert_eq!(kinds(&[1, 2, 3], &[1, 3]), "=-=");
+        assert_eq!(kinds(&[1, 3], &[1, 2, 3]), "=+=");
+        assert_eq!(kinds(&[1, 5, 5, 2, 3], &[1, 2, 5, 5, 3]), "=--=++=");
+        assert_eq!(kinds(&[7, 7, 8], &[7, 8, 8]), "=-+=");
+
+        let rows: Vec<DiffRow> = diff(&[1, 2, 3, 4], &[1, 9, 3, 8])
+            .into_iter()
+            .map(|(left, right, kind)| DiffRow { left, right, kind })
+            .collect();
+        assert_eq!(change_starts(&rows), vec![1, 4]);
+    }
+}
@@ -0,0 +1,249 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use regex::Regex;
+use std::fs;
+
+use crate::log_file::LogFile;
+
+/// Bar heights used by sparklines, lowest first
+const SPARK_CHARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Where the numbers of a series come from
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// A field of the parsed records
+    Field(String),
+    /// The first capture group of a regex (the whole match without groups)
+    Regex(Regex),
+}
+
+impl PartialEq for Source {
+    fn eq(&self, other: &Source) -> bool {
+        match (self, other) {
+            (Source::Field(a), Source::Field(b)) => a == b,
+            (Source::Regex(a), Source::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl Source {
+    /// `@name` (or a bare name while a format is active) is a field, anything
+    /// else a regex
+    pub fn parse(s: &str, structured: bool) -> Result<Source> {
+        if let Some(name) = s.strip_prefix('@') {
+            return Ok(Source::Field(name.to_string()));
+        }
+        let is_name = !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.');
+        if structured && is_name {
+            return Ok(Source::Field(s.to_string()));
+        }
+        Regex::new(s)
+            .map(Source::Regex)
+            .with_context(|| format!("Invalid regex {}", s))
+    }
+
+    fn value(&self, lf: &LogFile, line: &str) -> Option<f64> {
+        match self {
+            Source::Field(name) => {
+                let record = lf.format()?.parse(line)?;
+                parse_number(record.get(name)?)
+            }
+            Source::Regex(regex) => {
+                let caps = regex.captures(line)?;
+                let m = caps.get(1).or_else(|| caps.get(0))?;
+                parse_number(m.as_str())
+            }
+        }
+    }
+}
+
+/// Leading number of a value, so `12.5ms` reads as 12.5
+fn parse_number(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let end = s
+        .char_indices()
+        .find(|(i, c)| !(c.is_ascii_digit() || *c == '.' || (*i == 0 && (*c == '-' || *c == '+'))))
+        .map_or(s.len(), |(i, _)| i);
+    s[..end].parse().ok()
+}
+
+/// One extracted value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub line: usize,
+    pub timestamp: Option<NaiveDateTime>,
+    pub value: f64,
+}
+
+/// Collect the values of the visible records, in file order
+pub fn extract(lf: &LogFile, source: &Source) -> Vec<Point> {
+    let mut points = Vec::new();
+    let mut start = 0;
+    while start < lf.total_lines() {
+        if lf.is_line_visible(start) {
+            if let Some(line) = lf.get_line(start) {
+                if let Some(value) = source.value(lf, line) {
+                    let timestamp = lf
+                        .format()
+                        .and_then(|format| format.parse(line))
+                        .and_then(|record| record.timestamp);
+                    points.push(Point {
+                        line: start,
+                        timestamp,
+                        value,
+                    });
+                }
+            }
+        }
+        start = lf.record_end(start);
+    }
+    points
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+impl Summary {
+    pub fn of(points: &[Point]) -> Option<Summary> {
+        if points.is_empty() {
+            return None;
+        }
+        let mut values: Vec<f64> = points.iter().map(|p| p.value).collect();
+        values.sort_by(f64::total_cmp);
+
+        // Nearest-rank percentile
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * values.len() as f64).ceil() as usize;
+            values[rank.clamp(1, values.len()) - 1]
+        };
+
+        Some(Summary {
+            count: values.len(),
+            min: values[0],
+            max: values[values.len() - 1],
+            avg: values.iter().sum::<f64>() / values.len() as f64,
+            p50: percentile(50.0),
+            p95: percentile(95.0),
+            p99: percentile(99.0),
+        })
+    }
+
+    pub fn rows(&self) -> Vec<String> {
+        [
+            ("count", self.count as f64),
+            ("min", self.min),
+            ("max", self.max),
+            ("avg", self.avg),
+            ("p50", self.p50),
+            ("p95", self.p95),
+            ("p99", self.p99),
+        ]
+        .iter()
+        .map(|(name, value)| format!("  {:<6} {:>14}", name, format_value(*value)))
+        .collect()
+    }
+}
+
+/// A value without a fraction when it is whole
+pub fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:.3}", value)
+    }
+}
+
+/// The series drawn in at most `width` columns, averaging values that share a column
+pub fn sparkline(points: &[Point], width: usize) -> String {
+    if points.is_empty() || width == 0 {
+        return String::new();
+    }
+
+    let columns = points.len().min(width);
+    let averages: Vec<f64> = (0..columns)
+        .map(|c| {
+            let from = c * points.len() / columns;
+            let to = ((c + 1) * points.len() / columns).max(from + 1);
+            points[from..to].iter().map(|p| p.value).sum::<f64>() / (to - from) as f64
+        })
+        .collect();
+
+    let min = averages.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = averages.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let top = SPARK_CHARS.len() - 1;
+    averages
+        .iter()
+        .map(|v| {
+            let level = if max > min {
+                ((v - min) / (max - min) * top as f64).round() as usize
+            } else {
+                top / 2
+            };
+            SPARK_CHARS[level.min(top)]
+        })
+        .collect()
+}
+
+/// Write the series as CSV with a `line,timestamp,value` header; line numbers are 1-based
+pub fn export_csv(points: &[Point], path: &str) -> Result<()> {
+    let mut csv = String::from("line,timestamp,value\n");
+    for point in points {
+        let timestamp = point
+            .timestamp
+            .map(|ts| ts.format("%Y-%m-%dT%H:%M:%S%.3f").to_string())
+            .unwrap_or_default();
+        csv.push_str(&format!("{},{},{}\n", point.line + 1, timestamp, point.value));
+    }
+    fs::write(path, csv).with_context(|| format!("Failed to write {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_file::testing::open_test_log;
+
+    #[test]
+    fn test_extract() {
+        let content: String = ["12ms", "40ms", "8.5ms", "none", "100ms"]
+            .iter()
+            .map(|latency| format!("GET /api took {}\n", latency))
+            .collect();
+        let (_file, lf) = open_test_log(&content);
+
+        let source = Source::parse(r"took (\S+)", false).unwrap();
+        let points = extract(&lf, &source);
+        let values: Vec<f64> = points.iter().map(|p| p.value).collect();
+        assert_eq!(values, vec![12.0, 40.0, 8.5, 100.0]);
+        assert_eq!(points[3].line, 4);
+
+        let summary = Summary::of(&points).unwrap();
+        assert_eq!(summary.min, 8.5);
+        assert_eq!(summary.max, 100.0);
+        assert_eq!(summary.avg, 40.125);
+        assert_eq!(summary.p50, 12.0);
+        assert_eq!(summary.p99, 100.0);
+
+        assert_eq!(sparkline(&points, 10), "▁▃▁█");
+        assert_eq!(sparkline(&points, 2), "▁█");
+    }
+
+    #[test]
+    fn test_field_source() {
+        let (_file, lf) = open_test_log("{\"ts\":\"2024-01-02T10:00:00Z\",\"msg\":\"done\",\"duration\":\"250\"}\n");
+
+        let source = Source::parse("duration", lf.format().is_some()).unwrap();
+        let points = extract(&lf, &source);
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].value, 250.0);
+        assert!(points[0].timestamp.is_some());
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::paths;
+
+/// Entries kept per history, oldest dropped first
+const MAX_ENTRIES: usize = 1000;
+
+/// Previously entered prompt lines, oldest first, one per line in the file
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<String>,
+    /// Where the history is saved; `None` keeps it in memory only
+    path: Option<PathBuf>,
+}
+
+impl History {
+    /// History `name` in the data dir, or an in-memory one when there is no data dir
+    pub fn open(name: &str) -> Result<History> {
+        match paths::data_dir() {
+            Some(dir) => History::load(&dir.join("history").join(name)),
+            None => Ok(History::default()),
+        }
+    }
+
+    /// Load the history at `path`; a missing file means no history yet
+    pub fn load(path: &Path) -> Result<History> {
+        let entries = match fs::read_to_string(path) {
+            Ok(content) => content.lines().map(String::from).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(History {
+            entries,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    /// Add an entry as the most recent one, dropping any earlier copy, and save
+    pub fn add(&mut self, entry: &str) -> Result<()> {
+        if entry.trim().is_empty() {
+            return Ok(());
+        }
+        self.entries.retain(|e| e != entry);
+        self.entries.push(entry.to_string());
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.drain(..self.entries.len() - MAX_ENTRIES);
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let mut content = self.entries.join("\n");
+        content.push('\n');
+        fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Index of the newest entry before `before` that starts with `prefix`
+    pub fn prev(&self, prefix: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .rposition(|e| e.starts_with(prefix))
+    }
+
+    /// Index of the oldest entry after `after` that starts with `prefix`
+    pub fn next(&self, prefix: &str, after: usize) -> Option<usize> {
+        self.entries
+            .iter()
+            .enumerate()
+            .skip(after + 1)
+            .find(|(_, e)| e.starts_with(prefix))
+            .map(|(i, _)| i)
+    }
+
+    /// Index of the newest entry before `before` that contains `query` (Ctrl-R)
+    pub fn search(&self, query: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .rposition(|e| e.contains(query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history").join("commands");
+        let mut history = History::load(&path).unwrap();
+        for entry in ["hide DEBUG", "stats", "highlight error", "hide INFO", "stats", ""] {
+            history.add(entry).unwrap();
+        }
+
+        let history = History::load(&path).unwrap();
+        assert_eq!(history.len(), 4);
+        assert_eq!(history.get(3), Some("stats"));
+
+        let end = history.len();
+        assert_eq!(history.prev("hi", end), Some(2));
+        assert_eq!(history.prev("hi", 2), Some(1));
+        assert_eq!(history.prev("hide", 1), Some(0));
+        assert_eq!(history.prev("hide", 0), None);
+        assert_eq!(history.next("hide", 0), Some(2));
+        assert_eq!(history.next("hide", 2), None);
+        assert_eq!(history.search("err", end), Some(1));
+        assert_eq!(history.search("DEB", 0), None);
+    }
+}
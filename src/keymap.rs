@@ -0,0 +1,313 @@
+use anyhow::{anyhow, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+
+/// Something a key can do in the main view
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    CommandPrompt,
+    SearchForward,
+    SearchBackward,
+    SearchNext,
+    SearchPrev,
+    LineDown,
+    LineUp,
+    Left,
+    Right,
+    PageDown,
+    PageUp,
+    Top,
+    Bottom,
+    ToggleExpand,
+    ToggleTable,
+    NextError,
+    PrevError,
+    NextAnomaly,
+    PrevAnomaly,
+    Unfold,
+    CycleTimeline,
+    TimelinePrev,
+    TimelineNext,
+    SetMark,
+    JumpToMark,
+    HideLine,
+}
+
+/// Names used in the config and by `map`
+const ACTION_NAMES: [(&str, Action); 27] = [
+    ("quit", Action::Quit),
+    ("command-prompt", Action::CommandPrompt),
+    ("search-forward", Action::SearchForward),
+    ("search-backward", Action::SearchBackward),
+    ("search-next", Action::SearchNext),
+    ("search-prev", Action::SearchPrev),
+    ("line-down", Action::LineDown),
+    ("line-up", Action::LineUp),
+    ("left", Action::Left),
+    ("right", Action::Right),
+    ("page-down", Action::PageDown),
+    ("page-up", Action::PageUp),
+    ("top", Action::Top),
+    ("bottom", Action::Bottom),
+    ("toggle-expand", Action::ToggleExpand),
+    ("toggle-table", Action::ToggleTable),
+    ("next-error", Action::NextError),
+    ("prev-error", Action::PrevError),
+    ("next-anomaly", Action::NextAnomaly),
+    ("prev-anomaly", Action::PrevAnomaly),
+    ("unfold", Action::Unfold),
+    ("cycle-timeline", Action::CycleTimeline),
+    ("timeline-prev", Action::TimelinePrev),
+    ("timeline-next", Action::TimelineNext),
+    ("set-mark", Action::SetMark),
+    ("jump-to-mark", Action::JumpToMark),
+    ("hide-line", Action::HideLine),
+];
+
+/// Built-in bindings, in the notation of `parse_keys`
+const DEFAULT_BINDINGS: [(&str, Action); 37] = [
+    ("q", Action::Quit),
+    ("<Esc>", Action::CommandPrompt),
+    ("/", Action::SearchForward),
+    ("?", Action::SearchBackward),
+    ("n", Action::SearchNext),
+    ("N", Action::SearchPrev),
+    ("j", Action::LineDown),
+    ("<Down>", Action::LineDown),
+    ("k", Action::LineUp),
+    ("<Up>", Action::LineUp),
+    ("h", Action::Left),
+    ("<Left>", Action::Left),
+    ("l", Action::Right),
+    ("<Right>", Action::Right),
+    ("<Space>", Action::PageDown),
+    ("f", Action::PageDown),
+    ("<PageDown>", Action::PageDown),
+    ("<C-Space>", Action::PageUp),
+    ("b", Action::PageUp),
+    ("<PageUp>", Action::PageUp),
+    ("gg", Action::Top),
+    ("<lt>", Action::Top),
+    ("G", Action::Bottom),
+    ("<gt>", Action::Bottom),
+    ("e", Action::ToggleExpand),
+    ("t", Action::ToggleTable),
+    ("]e", Action::NextError),
+    ("[e", Action::PrevError),
+    ("a", Action::NextAnomaly),
+    ("A", Action::PrevAnomaly),
+    ("z", Action::Unfold),
+    ("T", Action::CycleTimeline),
+    ("H", Action::TimelinePrev),
+    ("L", Action::TimelineNext),
+    ("m", Action::SetMark),
+    ("'", Action::JumpToMark),
+    ("x", Action::HideLine),
+];
+
+impl Action {
+    pub fn parse(name: &str) -> Option<Action> {
+        ACTION_NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, action)| *action)
+    }
+}
+
+/// A key press with the modifiers that matter for bindings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub code: KeyCode,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+impl Key {
+    fn new(code: KeyCode) -> Key {
+        Key {
+            code,
+            ctrl: false,
+            alt: false,
+        }
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Key {
+        Key {
+            code: event.code,
+            ctrl: event.modifiers.contains(KeyModifiers::CONTROL),
+            alt: event.modifiers.contains(KeyModifiers::ALT),
+        }
+    }
+}
+
+/// Parse a key sequence in vim notation: plain characters, or names in angle
+/// brackets such as `<Esc>`, `<C-d>`, `<A-j>`, `<Space>`, `<lt>`
+pub fn parse_keys(keys: &str) -> Result<Vec<Key>> {
+    let mut sequence = Vec::new();
+    let mut rest = keys;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>').filter(|end| *end > 1) {
+                sequence.push(parse_named_key(&rest[1..end])?);
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+        sequence.push(Key::new(KeyCode::Char(c)));
+        rest = &rest[c.len_utf8()..];
+    }
+
+    if sequence.is_empty() {
+        return Err(anyhow!("Empty key sequence"));
+    }
+    Ok(sequence)
+}
+
+fn parse_named_key(name: &str) -> Result<Key> {
+    let (ctrl, alt, base) = match name.split_once('-') {
+        Some((modifier, base)) if !base.is_empty() => match modifier.to_lowercase().as_str() {
+            "c" => (true, false, base),
+            "a" | "m" => (false, true, base),
+            _ => return Err(anyhow!("Invalid key <{}>", name)),
+        },
+        _ => (false, false, name),
+    };
+
+    let code = match base.to_lowercase().as_str() {
+        "esc" => KeyCode::Esc,
+        "enter" | "cr" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "bs" | "backspace" => KeyCode::Backspace,
+        "del" | "delete" => KeyCode::Delete,
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "gt" => KeyCode::Char('>'),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        _ if base.chars().count() == 1 => KeyCode::Char(base.chars().next().unwrap()),
+        _ => match base.strip_prefix(['f', 'F']).and_then(|n| n.parse().ok()) {
+            Some(n) => KeyCode::F(n),
+            None => return Err(anyhow!("Invalid key <{}>", name)),
+        },
+    };
+    Ok(Key { code, ctrl, alt })
+}
+
+/// What a key sequence is bound to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Binding {
+    Action(Action),
+    /// A command, as typed at the prompt
+    Command(String),
+}
+
+impl Binding {
+    /// An action name, or else a command (a leading `:` is dropped)
+    pub fn parse(target: &str) -> Binding {
+        match Action::parse(target) {
+            Some(action) => Binding::Action(action),
+            None => Binding::Command(target.strip_prefix(':').unwrap_or(target).to_string()),
+        }
+    }
+}
+
+/// Result of looking up the keys typed so far
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    Found(Binding),
+    /// The keys start a longer binding; wait for more
+    Prefix,
+    None,
+}
+
+pub struct Keymap {
+    bindings: HashMap<Vec<Key>, Binding>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Keymap {
+            bindings: HashMap::new(),
+        };
+        for (keys, action) in DEFAULT_BINDINGS {
+            let keys = parse_keys(keys).expect("valid default binding");
+            keymap.bindings.insert(keys, Binding::Action(action));
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    /// Bind a key sequence, replacing whatever it was bound to
+    pub fn map(&mut self, keys: &str, target: &str) -> Result<()> {
+        let keys = parse_keys(keys)?;
+        self.bindings.insert(keys, Binding::parse(target));
+        Ok(())
+    }
+
+    pub fn lookup(&self, keys: &[Key]) -> Lookup {
+        if let Some(binding) = self.bindings.get(keys) {
+            return Lookup::Found(binding.clone());
+        }
+        if self
+            .bindings
+            .keys()
+            .any(|bound| bound.len() > keys.len() && bound.starts_with(keys))
+        {
+            Lookup::Prefix
+        } else {
+            Lookup::None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keymap() {
+        assert_eq!(
+            parse_keys("g<C-d><lt>").unwrap(),
+            vec![
+                Key::new(KeyCode::Char('g')),
+                Key {
+                    code: KeyCode::Char('d'),
+                    ctrl: true,
+                    alt: false
+                },
+                Key::new(KeyCode::Char('<')),
+            ]
+        );
+        assert!(parse_keys("<Foo>").is_err());
+
+        let mut keymap = Keymap::default();
+        let g = parse_keys("g").unwrap();
+        assert_eq!(keymap.lookup(&g), Lookup::Prefix);
+        assert_eq!(
+            keymap.lookup(&parse_keys("gg").unwrap()),
+            Lookup::Found(Binding::Action(Action::Top))
+        );
+        assert_eq!(keymap.lookup(&parse_keys("gx").unwrap()), Lookup::None);
+
+        keymap.map("<C-e>", ":hide DEBUG").unwrap();
+        keymap.map("J", "page-down").unwrap();
+        assert_eq!(
+            keymap.lookup(&parse_keys("<C-e>").unwrap()),
+            Lookup::Found(Binding::Command("hide DEBUG".to_string()))
+        );
+        assert_eq!(
+            keymap.lookup(&parse_keys("J").unwrap()),
+            Lookup::Found(Binding::Action(Action::PageDown))
+        );
+    }
+}
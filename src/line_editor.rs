@@ -0,0 +1,177 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Single-line text input with a cursor, used by the command prompt.
+///
+/// Positions are in characters; every character is assumed to take one column.
+#[derive(Debug, Clone, Default)]
+pub struct LineEditor {
+    chars: Vec<char>,
+    cursor: usize,
+    /// First character shown when the text is wider than the screen
+    scroll: usize,
+}
+
+impl LineEditor {
+    pub fn new(text: &str) -> Self {
+        let chars: Vec<char> = text.chars().collect();
+        LineEditor {
+            cursor: chars.len(),
+            chars,
+            scroll: 0,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    /// Replace the text, moving the cursor to its end
+    pub fn set_text(&mut self, text: &str) {
+        *self = LineEditor::new(text);
+    }
+
+    pub fn before_cursor(&self) -> String {
+        self.chars[..self.cursor].iter().collect()
+    }
+
+    /// Replace the text from character `start` up to the cursor, which ends up after `text`
+    pub fn replace_before_cursor(&mut self, start: usize, text: &str) {
+        let start = start.min(self.cursor);
+        let replacement: Vec<char> = text.chars().collect();
+        let len = replacement.len();
+        self.chars.splice(start..self.cursor, replacement);
+        self.cursor = start + len;
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    /// Insert pasted text; line breaks become spaces
+    pub fn insert_str(&mut self, text: &str) {
+        for c in text.chars() {
+            self.insert(if c == '\n' || c == '\r' { ' ' } else { c });
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    /// Delete the word before the cursor, and the spaces after it (Ctrl-W)
+    pub fn delete_word(&mut self) {
+        let mut start = self.cursor;
+        while start > 0 && self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.chars.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    /// Delete everything before the cursor (Ctrl-U)
+    pub fn delete_to_start(&mut self) {
+        self.chars.drain(..self.cursor);
+        self.cursor = 0;
+    }
+
+    /// Apply an editing or cursor key. Returns false for keys it does not handle.
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('w') if ctrl => self.delete_word(),
+            KeyCode::Char('u') if ctrl => self.delete_to_start(),
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.chars.len(),
+            KeyCode::Char('k') if ctrl => self.chars.truncate(self.cursor),
+            KeyCode::Char(_) if ctrl => return false,
+            KeyCode::Char(c) => self.insert(c),
+            KeyCode::Backspace => self.backspace(),
+            KeyCode::Delete => self.delete(),
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.chars.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.chars.len(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// The part of the text that fits in `width` columns, scrolled to keep the
+    /// cursor in view, and the cursor column within it
+    pub fn view(&mut self, width: usize) -> (String, usize) {
+        let width = width.max(1);
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        } else if self.cursor >= self.scroll + width {
+            // Leave the last column for the cursor past the end of the text
+            self.scroll = self.cursor + 1 - width;
+        }
+        let visible = self.chars.iter().skip(self.scroll).take(width).collect();
+        (visible, self.cursor - self.scroll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut editor = LineEditor::new("hide foo bar");
+        editor.delete_word();
+        assert_eq!(editor.text(), "hide foo ");
+
+        editor.handle_key(key(KeyCode::Home, KeyModifiers::NONE));
+        editor.handle_key(key(KeyCode::Delete, KeyModifiers::NONE));
+        editor.handle_key(key(KeyCode::Char('H'), KeyModifiers::SHIFT));
+        assert_eq!(editor.text(), "Hide foo ");
+
+        editor.handle_key(key(KeyCode::End, KeyModifiers::NONE));
+        editor.handle_key(key(KeyCode::Left, KeyModifiers::NONE));
+        editor.handle_key(key(KeyCode::Backspace, KeyModifiers::NONE));
+        editor.insert_str("x\ny");
+        assert_eq!(editor.text(), "Hide fox y ");
+
+        editor.replace_before_cursor(5, "bar");
+        assert_eq!(editor.before_cursor(), "Hide bar");
+        assert_eq!(editor.text(), "Hide bar ");
+
+        assert!(editor.handle_key(key(KeyCode::Char('u'), KeyModifiers::CONTROL)));
+        assert_eq!(editor.text(), " ");
+        assert!(!editor.handle_key(key(KeyCode::Enter, KeyModifiers::NONE)));
+    }
+
+    #[test]
+    fn test_scrolling() {
+        let mut editor = LineEditor::new("0123456789");
+        assert_eq!(editor.view(4), ("789".to_string(), 3));
+
+        editor.handle_key(key(KeyCode::Home, KeyModifiers::NONE));
+        assert_eq!(editor.view(4), ("0123".to_string(), 0));
+
+        editor.handle_key(key(KeyCode::Char('k'), KeyModifiers::CONTROL));
+        assert_eq!(editor.view(4), ("".to_string(), 0));
+
+        editor.set_text("abcdefgh");
+        assert_eq!(editor.view(4), ("fgh".to_string(), 3));
+        editor.set_text("ab");
+        assert_eq!(editor.view(4), ("ab".to_string(), 2));
+    }
+}
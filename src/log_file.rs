@@ -1,26 +1,110 @@
 #![allow(dead_code)]
 
+use anyhow::{Context, Result};
 use memmap2::Mmap;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io;
+use std::ops::Range;
 use std::path::Path;
 use log::{debug};
 
+use crate::log_format::{self, LogFormat};
+use crate::matcher::Matcher;
+use crate::normalize;
+use crate::query::Query;
+use crate::template;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SearchDirection {
     Forward,
     Backward,
 }
 
+/// Records checked between two calls of the cancel check of `search_cancellable`
+const CANCEL_CHECK_RECORDS: usize = 10_000;
+
+/// A search given up on by its caller
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl SearchDirection {
+    pub fn reversed(self) -> SearchDirection {
+        match self {
+            SearchDirection::Forward => SearchDirection::Backward,
+            SearchDirection::Backward => SearchDirection::Forward,
+        }
+    }
+}
+
+/// Leading timestamp shapes used by the "starts with timestamp" grouping rule
+const TIMESTAMP_START: &str = r"^\[?(\d{4}[-/]\d\d[-/]\d\d[T ]\d\d:\d\d|\d\d:\d\d:\d\d|[A-Z][a-z]{2} [ \d]\d \d\d:\d\d:\d\d|\d\d/[A-Z][a-z]{2}/\d{4}:)";
+
+/// Most bytes of the first line hashed into the file fingerprint
+const FINGERPRINT_BYTES: usize = 4096;
+
+/// How physical lines are grouped into logical records (e.g. a log line
+/// followed by its stack trace)
+#[derive(Debug, Clone)]
+pub enum GroupRule {
+    /// Every line is its own record
+    Off,
+    /// A record starts at each line beginning with a timestamp
+    Timestamp,
+    /// A record starts at each line matching the pattern
+    Pattern(Regex),
+}
+
+/// A filter as the user applied it. Filters are kept as rules rather than
+/// visibility bitmaps so they can be replayed on another version of the file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterRule {
+    /// Hide records matching a pattern (see `Matcher`)
+    Hide(String),
+    /// Show only records matching a pattern
+    Show(String),
+    /// Hide the record starting at this line
+    HideRecord(usize),
+    /// Hide records whose normalized message also appears in the file at this path
+    Baseline(String),
+    /// Hide records whose message fits a template (see `template::matches`)
+    HideTemplate(String),
+    /// Show only records whose message fits a template
+    ShowTemplate(String),
+    /// Hide the records behind the query result row with this key (see `Query::row_key`)
+    HideQuery { query: String, key: Vec<Option<String>> },
+    /// Show only the records behind a query result row
+    ShowQuery { query: String, key: Vec<Option<String>> },
+}
+
+/// How consecutive duplicate records are detected when folding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupMode {
+    /// Records must be identical
+    Exact,
+    /// Records must be identical once numbers, UUIDs and hex ids are masked
+    Masked,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Line<'a> {
     pub line_number: usize,
     pub data: &'a str,
+    /// Number of duplicate records folded into this one
+    pub folded: usize,
 }
 
 impl<'a> Line<'a> {
     pub fn new(line_number: usize, data: &'a str) -> Self {
-        Line { line_number, data }
+        Line {
+            line_number,
+            data,
+            folded: 0,
+        }
     }
 }
 
@@ -31,6 +115,22 @@ pub struct LogFile {
     line_visibility: Vec<bool>,
     backup_visibility: Option<Vec<bool>>,
     total_lines: usize,
+    format: Option<LogFormat>,
+    record_starts: Vec<bool>,
+    dedup: Option<DedupMode>,
+    folded: Vec<bool>,
+    folds: BTreeMap<usize, Vec<usize>>,
+    /// Filters applied since the last reset, in order
+    filters: Vec<FilterRule>,
+    /// User formats given to `detect_format`, also used to read baseline files
+    user_formats: Vec<LogFormat>,
+}
+
+/// FNV-1a hash, stable across runs and platforms so it can name files on disk
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
 }
 
 impl LogFile {
@@ -47,6 +147,13 @@ impl LogFile {
                 line_visibility: vec![true],
                 backup_visibility: None,
                 total_lines: 1,
+                format: None,
+                record_starts: vec![true],
+                dedup: None,
+                folded: vec![false],
+                folds: BTreeMap::new(),
+                filters: Vec::new(),
+                user_formats: Vec::new(),
             });
         }
 
@@ -87,6 +194,13 @@ impl LogFile {
             line_visibility,
             backup_visibility: None,
             total_lines,
+            format: None,
+            record_starts: vec![true; total_lines],
+            dedup: None,
+            folded: vec![false; total_lines],
+            folds: BTreeMap::new(),
+            filters: Vec::new(),
+            user_formats: Vec::new(),
         })
     }
 
@@ -95,11 +209,284 @@ impl LogFile {
         self.total_lines
     }
 
+    /// Format used to split lines into fields, if one was picked
+    pub fn format(&self) -> Option<&LogFormat> {
+        self.format.as_ref()
+    }
+
+    pub fn clear_format(&mut self) {
+        self.set_format(None);
+    }
+
+    /// Switch format; a format with a start pattern also regroups the records
+    fn set_format(&mut self, format: Option<LogFormat>) {
+        let rule = match format.as_ref().and_then(|f| f.start_pattern()) {
+            Some(pattern) => GroupRule::Pattern(pattern.clone()),
+            None => GroupRule::Off,
+        };
+        self.format = format;
+        self.set_group_rule(&rule);
+    }
+
+    /// First `count` lines of the file, used to guess its format
+    pub fn sample_lines(&self, count: usize) -> Vec<&str> {
+        (0..count.min(self.total_lines))
+            .filter_map(|i| self.get_line(i))
+            .collect()
+    }
+
+    /// Pick the best matching format among the user and built-in formats
+    pub fn detect_format(&mut self, user_formats: &[LogFormat]) {
+        let format = log_format::detect(user_formats, &self.sample_lines(log_format::SAMPLE_LINES));
+        self.user_formats = user_formats.to_vec();
+        self.set_format(format);
+    }
+
+    /// Switch to the format called `name`, returning false if there is none
+    pub fn set_format_by_name(&mut self, name: &str, user_formats: &[LogFormat]) -> bool {
+        let sample = self.sample_lines(log_format::SAMPLE_LINES);
+        let format = log_format::all_formats(user_formats, &sample)
+            .into_iter()
+            .find(|format| format.name() == name);
+
+        match format {
+            Some(format) => {
+                self.set_format(Some(format));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Regroup lines into records according to `rule`
+    pub fn set_group_rule(&mut self, rule: &GroupRule) {
+        let timestamp_start = Regex::new(TIMESTAMP_START).expect("valid timestamp regex");
+        let starts: Vec<bool> = (0..self.total_lines)
+            .map(|i| {
+                let line = self.get_line(i).unwrap_or_default();
+                i == 0
+                    || match rule {
+                        GroupRule::Off => true,
+                        GroupRule::Timestamp => timestamp_start.is_match(line),
+                        GroupRule::Pattern(pattern) => pattern.is_match(line),
+                    }
+            })
+            .collect();
+        self.record_starts = starts;
+        self.refold();
+    }
+
+    /// First line of the record containing `line_idx`
+    pub fn record_start(&self, line_idx: usize) -> usize {
+        let mut i = line_idx.min(self.total_lines.saturating_sub(1));
+        while i > 0 && !self.record_starts[i] {
+            i -= 1;
+        }
+        i
+    }
+
+    /// One past the last line of the record starting at `start`
+    pub fn record_end(&self, start: usize) -> usize {
+        let mut i = start + 1;
+        while i < self.total_lines && !self.record_starts[i] {
+            i += 1;
+        }
+        i.min(self.total_lines)
+    }
+
+    /// All lines of the record containing `line_idx`, joined with newlines
+    pub fn get_record(&self, line_idx: usize) -> Option<String> {
+        let start = self.record_start(line_idx);
+        let lines: Vec<&str> = (start..self.record_end(start))
+            .filter_map(|i| self.get_line(i))
+            .collect();
+        if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        }
+    }
+
+    /// Whether any line of the record starting at `start` satisfies the predicate
+    fn record_matches<F>(&self, start: usize, predicate: &F) -> bool
+    where
+        F: Fn(&str) -> bool,
+    {
+        (start..self.record_end(start)).any(|i| self.get_line(i).is_some_and(predicate))
+    }
+
+    fn set_record_visibility(&mut self, start: usize, visible: bool) {
+        let end = self.record_end(start);
+        self.line_visibility[start..end].fill(visible);
+    }
+
+    /// First line of each record whose first line satisfies the predicate
+    fn records_where<F>(&self, predicate: F) -> Vec<usize>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut starts = Vec::new();
+        let mut start = 0;
+        while start < self.total_lines {
+            if self.get_line(start).is_some_and(&predicate) {
+                starts.push(start);
+            }
+            start = self.record_end(start);
+        }
+        starts
+    }
+
+    /// Hide the records starting at the given lines, or with `show` every other record
+    fn filter_records(&mut self, starts: &[usize], show: bool) {
+        if show {
+            self.line_visibility.fill(false);
+        }
+        for &start in starts {
+            self.set_record_visibility(start, show);
+        }
+        self.refold();
+    }
+
+    /// Hide the whole record containing a line
+    pub fn hide_record(&mut self, line_idx: usize) -> Result<()> {
+        if line_idx < self.total_lines {
+            self.apply_filter(FilterRule::HideRecord(self.record_start(line_idx)))?;
+        }
+        Ok(())
+    }
+
+    /// Apply a filter and remember it
+    pub fn apply_filter(&mut self, rule: FilterRule) -> Result<()> {
+        match &rule {
+            FilterRule::Hide(pattern) => {
+                let matcher = Matcher::parse(pattern);
+                let format = self.format.clone();
+                self.hide_lines_matching(|line| matcher.matches(line, format.as_ref()));
+            }
+            FilterRule::Show(pattern) => {
+                let matcher = Matcher::parse(pattern);
+                let format = self.format.clone();
+                self.show_lines_matching(|line| matcher.matches(line, format.as_ref()));
+            }
+            FilterRule::HideRecord(line_idx) => {
+                // A shorter version of the file may not have the record
+                if *line_idx < self.total_lines {
+                    self.filter_records(&[self.record_start(*line_idx)], false);
+                }
+            }
+            FilterRule::Baseline(path) => {
+                let mut baseline = LogFile::new(path)
+                    .with_context(|| format!("Failed to open baseline {}", path))?;
+                baseline.detect_format(&self.user_formats);
+                let known = template::records_in_baseline(self, &baseline);
+                debug!("Hiding {} records found in baseline {}", known.len(), path);
+                self.filter_records(&known, false);
+            }
+            FilterRule::HideTemplate(text) | FilterRule::ShowTemplate(text) => {
+                let starts = self.records_where(|line| {
+                    template::matches(text, &template::message_of(self, line))
+                });
+                self.filter_records(&starts, matches!(rule, FilterRule::ShowTemplate(_)));
+            }
+            FilterRule::HideQuery { query, key } | FilterRule::ShowQuery { query, key } => {
+                let query = Query::parse(query)?;
+                let starts: Vec<usize> = self
+                    .records_where(|_| true)
+                    .into_iter()
+                    .filter(|&start| query.row_key(self, start).as_ref() == Some(key))
+                    .collect();
+                self.filter_records(&starts, matches!(rule, FilterRule::ShowQuery { .. }));
+            }
+        }
+        self.filters.push(rule);
+        Ok(())
+    }
+
+    /// Filters applied since the last reset, in order
+    pub fn filters(&self) -> &[FilterRule] {
+        &self.filters
+    }
+
+    pub fn dedup(&self) -> Option<DedupMode> {
+        self.dedup
+    }
+
+    /// Fold runs of consecutive duplicate records into their first record, or
+    /// stop folding with `None`
+    pub fn set_dedup(&mut self, mode: Option<DedupMode>) {
+        self.dedup = mode;
+        self.refold();
+    }
+
+    /// Recompute the folded runs among the currently visible records
+    fn refold(&mut self) {
+        self.folded.fill(false);
+        self.folds.clear();
+
+        let Some(mode) = self.dedup else {
+            return;
+        };
+
+        // Exact runs compare the lines in place; masked ones keep the masked
+        // lines of the run's head
+        let mut head: Option<(usize, Vec<String>)> = None;
+        let mut start = 0;
+        while start < self.total_lines {
+            let end = self.record_end(start);
+            if self.line_visibility[start] {
+                let key = match mode {
+                    DedupMode::Exact => Vec::new(),
+                    DedupMode::Masked => self.record_lines(start).map(normalize::mask).collect(),
+                };
+                let repeats = match &head {
+                    Some((head_start, _)) if mode == DedupMode::Exact => {
+                        self.record_lines(*head_start).eq(self.record_lines(start))
+                    }
+                    Some((_, head_key)) => *head_key == key,
+                    None => false,
+                };
+
+                match &head {
+                    Some((head_start, _)) if repeats => {
+                        self.folds.entry(*head_start).or_default().push(start);
+                        self.folded[start..end].fill(true);
+                    }
+                    _ => head = Some((start, key)),
+                }
+            }
+            start = end;
+        }
+    }
+
+    /// The lines of the record starting at `start`
+    fn record_lines(&self, start: usize) -> impl Iterator<Item = &str> + '_ {
+        (start..self.record_end(start)).filter_map(|i| self.get_line(i))
+    }
+
+    /// Expand the folded run headed by the record containing `line_idx`.
+    /// Returns false if there was nothing to expand.
+    pub fn unfold(&mut self, line_idx: usize) -> bool {
+        let start = self.record_start(line_idx);
+        let Some(members) = self.folds.remove(&start) else {
+            return false;
+        };
+
+        for member in members {
+            let end = self.record_end(member);
+            self.folded[member..end].fill(false);
+        }
+        true
+    }
+
+    /// Number of duplicate records folded into the record starting at `line_idx`
+    pub fn folded_count(&self, line_idx: usize) -> usize {
+        self.folds.get(&line_idx).map_or(0, Vec::len)
+    }
+
     /// Get the number of currently visible lines
     pub fn visible_lines(&self) -> usize {
-        self.line_visibility
-            .iter()
-            .filter(|&&visible| visible)
+        (0..self.total_lines)
+            .filter(|&i| self.is_line_visible(i))
             .count()
     }
 
@@ -133,6 +520,7 @@ impl LogFile {
     /// Check if a line is visible
     pub fn is_line_visible(&self, line_idx: usize) -> bool {
         self.line_visibility.get(line_idx).copied().unwrap_or(false)
+            && !self.folded.get(line_idx).copied().unwrap_or(false)
     }
 
     /// Hide a line
@@ -154,9 +542,11 @@ impl LogFile {
         self.line_visibility.fill(false);
     }
 
-    /// Show all lines
+    /// Show all lines, dropping the filters
     pub fn show_all(&mut self) {
+        self.filters.clear();
         self.line_visibility.fill(true);
+        self.refold();
     }
 
     pub fn show_single_line(&mut self, line_idx: usize) {
@@ -167,38 +557,39 @@ impl LogFile {
         }
     }
 
-    /// Hide lines matching a predicate
+    /// Hide records with any line matching a predicate
     pub fn hide_lines_matching<F>(&mut self, predicate: F)
     where
         F: Fn(&str) -> bool,
     {
-        for i in 0..self.total_lines {
-            if let Some(line) = self.get_line(i) {
-                if predicate(line) {
-                    self.line_visibility[i] = false;
-                }
+        let mut start = 0;
+        while start < self.total_lines {
+            let end = self.record_end(start);
+            if self.record_matches(start, &predicate) {
+                self.set_record_visibility(start, false);
             }
+            start = end;
         }
+        self.refold();
     }
 
-    /// Show lines matching a predicate
+    /// Show only records with any line matching a predicate
     pub fn show_lines_matching<F>(&mut self, predicate: F)
     where
         F: Fn(&str) -> bool,
     {
-        for i in 0..self.total_lines {
-            if let Some(line) = self.get_line(i) {
-                if predicate(line) {
-                    self.line_visibility[i] = true;
-                } else {
-                    self.line_visibility[i] = false;
-                }
-            }
+        let mut start = 0;
+        while start < self.total_lines {
+            let end = self.record_end(start);
+            let visible = self.record_matches(start, &predicate);
+            self.set_record_visibility(start, visible);
+            start = end;
         }
+        self.refold();
     }
 
     /// Get a range of visible lines for display
-    pub fn get_visible_lines(&self, start_indx: usize, count: usize) -> Vec<Line> {
+    pub fn get_visible_lines(&self, start_indx: usize, count: usize) -> Vec<Line<'_>> {
         let mut result = Vec::new();
         let mut visible_count = 0;
 
@@ -211,7 +602,9 @@ impl LogFile {
             }
 
             if let Some(line) = self.get_line(i) {
-                result.push(Line::new(i, line));
+                let mut line = Line::new(i, line);
+                line.folded = self.folded_count(i);
+                result.push(line);
                 visible_count += 1;
                 if visible_count >= count {
                     break;
@@ -238,20 +631,20 @@ impl LogFile {
         result
     }
 
-    pub fn num_lines_to_print(line_len: usize, cols: usize, max_lines:usize, rows_left: usize) -> usize {
-        let num_lines_to_print = if line_len == 0 {
-            1
-        } else {
-            line_len / cols + if line_len % cols > 0 { 1 } else { 0 }
-        };
-        num_lines_to_print.min(max_lines).min(rows_left)
-    }
-
-    pub fn get_end_of_file(&self, rows: usize, cols: usize, max_row_per_line: usize) -> (usize, usize) {
-        self.get_pos_from_end_line(self.total_lines, rows, cols, max_row_per_line)
+    pub fn get_end_of_file<F>(&self, rows: usize, line_rows: F) -> (usize, usize)
+    where
+        F: Fn(usize) -> usize,
+    {
+        self.get_pos_from_end_line(self.total_lines, rows, line_rows)
     }
 
-    pub fn get_pos_from_end_line(&self, end_pos: usize, rows: usize, cols: usize, max_row_per_line: usize) -> (usize, usize) {
+    /// First and last line of a screen of `rows` rows ending with the last
+    /// visible line before `end_pos`. `line_rows` gives the rows a line takes
+    /// on screen, so wrapped lines and table rows are counted as drawn.
+    pub fn get_pos_from_end_line<F>(&self, end_pos: usize, rows: usize, line_rows: F) -> (usize, usize)
+    where
+        F: Fn(usize) -> usize,
+    {
 
         let mut start_line = None;
         let mut end_line = None;
@@ -263,21 +656,11 @@ impl LogFile {
         }
 
         if let Some(end_line) = end_line {
-            let mut row_count = Self::num_lines_to_print(
-                self.line_lengths[end_line],
-                cols,
-                max_row_per_line,
-                rows,
-            );
+            let mut row_count = line_rows(end_line).min(rows);
 
             for i in (0..end_line).rev() {
                 if self.is_line_visible(i) {
-                    row_count += Self::num_lines_to_print(
-                        self.line_lengths[i],
-                        cols,
-                        max_row_per_line,
-                        max_row_per_line,
-                    );
+                    row_count += line_rows(i);
                 }
 
 		debug!("EOF row_count {}  rows{}", row_count, rows);
@@ -292,9 +675,9 @@ impl LogFile {
 
             if let Some(start_line) = start_line {
 	        debug!("Get end of file {} {}", start_line, end_line);
-                return (start_line, end_line);
+                (start_line, end_line)
             } else {
-                return (end_line, end_line); 
+                (end_line, end_line)
             }
 
         } else {
@@ -302,6 +685,27 @@ impl LogFile {
         }
     }
 
+    /// First lines of the visible records matching `pattern`, scanning from the
+    /// record starting at `from` over about `max_lines` lines. Returns them and
+    /// where to carry on, which is `total_lines()` at the end of the file.
+    pub fn find_matches(&self, pattern: &str, from: usize, max_lines: usize) -> (Vec<usize>, usize) {
+        let matcher = Matcher::parse(pattern);
+        let predicate = |line: &str| matcher.matches(line, self.format.as_ref());
+
+        let mut matches = Vec::new();
+        let mut start = from;
+        let end = from.saturating_add(max_lines).min(self.total_lines);
+        while start < end {
+            if self.is_line_visible(start) && self.record_matches(start, &predicate) {
+                matches.push(start);
+            }
+            start = self.record_end(start);
+        }
+        (matches, start)
+    }
+
+    /// Find the next record matching `pattern`, returning its first line.
+    /// The record containing `line_num` is only considered when `search_current_line` is set.
     pub fn search(
         &self,
         pattern: &str,
@@ -309,46 +713,114 @@ impl LogFile {
         search_current_line: bool,
         direction: SearchDirection,
     ) -> Option<usize> {
+        self.search_cancellable(pattern, line_num, search_current_line, direction, || false)
+            .unwrap_or(None)
+    }
 
-        let offset = if search_current_line {
-            0
-        } else {
-            1 // Start searching from the next line
+    /// `search` that asks `cancelled` every `CANCEL_CHECK_RECORDS` records
+    /// whether to give up, so typing stays responsive on huge files
+    pub fn search_cancellable(
+        &self,
+        pattern: &str,
+        line_num: usize,
+        search_current_line: bool,
+        direction: SearchDirection,
+        cancelled: impl FnMut() -> bool,
+    ) -> Result<Option<usize>, Cancelled> {
+        let range = self.search_range(line_num, search_current_line, direction);
+        self.search_records(pattern, range, direction, cancelled)
+    }
+
+    /// `search_cancellable` that carries on from the other end of the file when
+    /// nothing is found; the flag tells whether it had to
+    pub fn search_wrapping(
+        &self,
+        pattern: &str,
+        line_num: usize,
+        search_current_line: bool,
+        direction: SearchDirection,
+        mut cancelled: impl FnMut() -> bool,
+    ) -> Result<Option<(usize, bool)>, Cancelled> {
+        let range = self.search_range(line_num, search_current_line, direction);
+        if let Some(found) = self.search_records(pattern, range.clone(), direction, &mut cancelled)? {
+            return Ok(Some((found, false)));
+        }
+        // The rest of the file, up to where the first pass started
+        let rest = match direction {
+            SearchDirection::Forward => 0..range.start,
+            SearchDirection::Backward => range.end..self.total_lines,
         };
+        let found = self.search_records(pattern, rest, direction, cancelled)?;
+        Ok(found.map(|line| (line, true)))
+    }
 
+    /// Lines whose records a search from `line_num` looks at before wrapping
+    fn search_range(
+        &self,
+        line_num: usize,
+        search_current_line: bool,
+        direction: SearchDirection,
+    ) -> Range<usize> {
+        let start = self.record_start(line_num);
+        match (direction, search_current_line) {
+            (SearchDirection::Forward, true) => start..self.total_lines,
+            (SearchDirection::Forward, false) => self.record_end(start)..self.total_lines,
+            (SearchDirection::Backward, true) => 0..self.record_end(start),
+            (SearchDirection::Backward, false) => 0..start,
+        }
+    }
+
+    /// First record starting in `range` that matches `pattern`, scanning in
+    /// `direction`
+    fn search_records(
+        &self,
+        pattern: &str,
+        range: Range<usize>,
+        direction: SearchDirection,
+        mut cancelled: impl FnMut() -> bool,
+    ) -> Result<Option<usize>, Cancelled> {
+        let end = range.end.min(self.total_lines);
+        if range.start >= end {
+            return Ok(None);
+        }
+
+        let matcher = Matcher::parse(pattern);
+        let predicate = |line: &str| matcher.matches(line, self.format.as_ref());
+        let mut checked = 0;
+        let mut is_match = |start: usize| {
+            checked += 1;
+            if checked % CANCEL_CHECK_RECORDS == 0 && cancelled() {
+                return Err(Cancelled);
+            }
+            // Skip hidden records
+            Ok(self.is_line_visible(start) && self.record_matches(start, &predicate))
+        };
 
         match direction {
             SearchDirection::Forward => {
-                for i in (line_num + offset)..self.total_lines {
-                    
-                    if !self.is_line_visible(i) {
-                        continue; // Skip hidden lines
-                    }
-
-                    if let Some(line) = self.get_line(i) {
-                        debug!("Checking line {}: {}", i, line);
-                        if line.contains(pattern) {
-                            debug!("Found pattern '{}' in line {}", pattern, i);
-                            return Some(i);
-                        }
+                let mut start = self.record_start(range.start);
+                while start < end {
+                    if is_match(start)? {
+                        debug!("Found pattern '{}' in line {}", pattern, start);
+                        return Ok(Some(start));
                     }
+                    start = self.record_end(start);
                 }
             }
             SearchDirection::Backward => {
-                for i in (0..=(line_num - offset)).rev() {
-                    if !self.is_line_visible(i) {
-                        continue; // Skip hidden lines
+                let mut start = self.record_start(end - 1);
+                loop {
+                    if is_match(start)? {
+                        return Ok(Some(start));
                     }
-
-                    if let Some(line) = self.get_line(i) {
-                        if line.contains(pattern) {
-                            return Some(i);
-                        }
+                    if start <= range.start {
+                        break;
                     }
+                    start = self.record_start(start - 1);
                 }
             }
         }
-        None
+        Ok(None)
     }
 
     /// Get file size in bytes
@@ -356,6 +828,23 @@ impl LogFile {
         self.mmap.len()
     }
 
+    /// Byte offset of the start of a line
+    pub fn line_offset(&self, line_idx: usize) -> Option<usize> {
+        self.line_starts.get(line_idx).copied()
+    }
+
+    /// The line starting exactly at a byte offset
+    pub fn line_at_offset(&self, offset: usize) -> Option<usize> {
+        self.line_starts.binary_search(&offset).ok()
+    }
+
+    /// Stable hash (FNV-1a) of the first line and so of the file, since the
+    /// first line is usually timestamped. Appending to a log keeps its fingerprint.
+    pub fn fingerprint(&self) -> String {
+        let head = self.get_line_bytes(0).unwrap_or_default();
+        format!("{:016x}", fnv1a(&head[..head.len().min(FINGERPRINT_BYTES)]))
+    }
+
     /// Get the raw bytes for a line (useful for binary data or non-UTF8)
     pub fn get_line_bytes(&self, line_idx: usize) -> Option<&[u8]> {
         if line_idx >= self.total_lines {
@@ -377,19 +866,35 @@ impl LogFile {
     }
 }
 
+/// Logs on disk for the tests of every module
 #[cfg(test)]
-mod tests {
-    use super::*;
+pub mod testing {
+    use super::LogFile;
     use std::io::Write;
     use tempfile::NamedTempFile;
 
-    fn create_test_file(content: &str) -> NamedTempFile {
+    pub fn create_test_file(content: &str) -> NamedTempFile {
         let mut file = NamedTempFile::new().unwrap();
         file.write_all(content.as_bytes()).unwrap();
         file.flush().unwrap();
         file
     }
 
+    /// A log of `content` with its format detected among the built-in ones.
+    /// The file must outlive the log.
+    pub fn open_test_log(content: &str) -> (NamedTempFile, LogFile) {
+        let file = create_test_file(content);
+        let mut lf = LogFile::new(file.path()).unwrap();
+        lf.detect_format(&[]);
+        (file, lf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::create_test_file;
+    use super::*;
+
     #[test]
     fn test_basic_functionality() {
         let test_content = "Line 1\nLine 2\nLine 3\n";
@@ -452,6 +957,122 @@ Error: another issue
 
         let info_lines = viewer.search("Info", 0, true, SearchDirection::Forward);
         assert_eq!(info_lines, Some(1));
+
+        assert_eq!(viewer.find_matches("Error", 0, 2), (vec![0], 2));
+        assert_eq!(viewer.find_matches("Error", 2, 2), (vec![2], 3));
+
+        let wrapped = viewer.search_wrapping("Info", 2, true, SearchDirection::Forward, || false);
+        assert_eq!(wrapped, Ok(Some((1, true))));
+        let wrapped = viewer.search_wrapping("Error", 1, false, SearchDirection::Backward, || false);
+        assert_eq!(wrapped, Ok(Some((0, false))));
+        assert_eq!(viewer.search_wrapping("Debug", 0, true, SearchDirection::Forward, || false), Ok(None));
+
+        // A search with no match reads each record once, wrapping or not
+        let noise = create_test_file(&"noise\n".repeat(2 * CANCEL_CHECK_RECORDS));
+        let noise = LogFile::new(noise.path()).unwrap();
+        let halves = [
+            (CANCEL_CHECK_RECORDS, SearchDirection::Forward),
+            (CANCEL_CHECK_RECORDS - 1, SearchDirection::Backward),
+        ];
+        for (origin, direction) in halves {
+            let mut checks = 0;
+            let found = noise.search_wrapping("needle", origin, true, direction, || {
+                checks += 1;
+                false
+            });
+            assert_eq!((found, checks), (Ok(None), 2));
+        }
+
+        let many = "noise\n".repeat(CANCEL_CHECK_RECORDS) + "needle\n";
+        let file = create_test_file(&many);
+        let viewer = LogFile::new(file.path()).unwrap();
+        let found = viewer.search_cancellable("needle", 0, true, SearchDirection::Forward, || true);
+        assert_eq!(found, Err(Cancelled));
+        let found = viewer.search_cancellable("needle", 0, true, SearchDirection::Forward, || false);
+        assert_eq!(found, Ok(Some(CANCEL_CHECK_RECORDS)));
+    }
+
+    #[test]
+    fn test_record_grouping() {
+        let test_content = "2024-01-02 10:00:00 INFO starting
+2024-01-02 10:00:01 ERROR request failed
+java.lang.NullPointerException: boom
+    at com.example.App.run(App.java:10)
+2024-01-02 10:00:02 INFO done
+";
+        let file = create_test_file(test_content);
+
+        let mut viewer = LogFile::new(file.path()).unwrap();
+        viewer.set_group_rule(&GroupRule::Timestamp);
+
+        assert_eq!(viewer.record_start(3), 1);
+        assert_eq!(viewer.record_end(1), 4);
+
+        viewer.show_lines_matching(|line| line.contains("NullPointerException"));
+        let visible: Vec<usize> = viewer
+            .get_visible_lines(0, 10)
+            .iter()
+            .map(|line| line.line_number)
+            .collect();
+        assert_eq!(visible, vec![1, 2, 3]);
+
+        viewer.show_all();
+        assert_eq!(viewer.search("App.java", 0, true, SearchDirection::Forward), Some(1));
+        assert_eq!(viewer.search("INFO", 1, false, SearchDirection::Forward), Some(4));
+        assert_eq!(viewer.search("INFO", 3, false, SearchDirection::Backward), Some(0));
+
+        viewer.hide_record(2).unwrap();
+        assert_eq!(viewer.visible_lines(), 2);
+        assert_eq!(viewer.filters(), &[FilterRule::HideRecord(1)]);
+
+        // Rules replay on another version of the file
+        let other = create_test_file("2024-01-03 09:00:00 INFO starting\nnext\n2024-01-03 09:00:05 ERROR request timed out\n");
+        let mut other = LogFile::new(other.path()).unwrap();
+        for rule in viewer.filters().to_vec() {
+            other.apply_filter(rule).unwrap();
+        }
+        assert_eq!(other.visible_lines(), 2);
+        assert!(!other.is_line_visible(1));
+
+        other.show_all();
+        other.apply_filter(FilterRule::ShowTemplate("<*> <*> ERROR request <*> <*>".to_string())).unwrap();
+        assert_eq!(other.visible_lines(), 1);
+        assert!(other.is_line_visible(2));
+
+        other.show_all();
+        other.apply_filter(FilterRule::Baseline(file.path().to_string_lossy().into_owned())).unwrap();
+        assert_eq!(other.visible_lines(), 2);
+        assert!(!other.is_line_visible(0));
+        assert!(other.apply_filter(FilterRule::Baseline("/missing.log".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_dedup() {
+        let test_content = "start
+10:00:01 retry 1 failed
+10:00:02 retry 2 failed
+10:00:02 retry 2 failed
+done
+";
+        let file = create_test_file(test_content);
+
+        let mut viewer = LogFile::new(file.path()).unwrap();
+        viewer.set_dedup(Some(DedupMode::Exact));
+        assert_eq!(viewer.visible_lines(), 4);
+        assert_eq!(viewer.folded_count(2), 1);
+
+        viewer.set_dedup(Some(DedupMode::Masked));
+        assert_eq!(viewer.visible_lines(), 3);
+        let visible = viewer.get_visible_lines(0, 10);
+        assert_eq!(visible[1].line_number, 1);
+        assert_eq!(visible[1].folded, 2);
+
+        assert!(viewer.unfold(1));
+        assert_eq!(viewer.visible_lines(), 5);
+        assert!(!viewer.unfold(1));
+
+        viewer.set_dedup(None);
+        assert_eq!(viewer.visible_lines(), 5);
     }
 
     #[test]
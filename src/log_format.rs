@@ -0,0 +1,675 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Local, NaiveDateTime};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use log::debug;
+
+/// Timestamp layouts tried when a format does not list its own
+const DEFAULT_TIMESTAMP_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.fZ",
+    "%Y-%m-%dT%H:%M:%S%.f%:z",
+    "%Y-%m-%d %H:%M:%S,%3f",
+    "%Y/%m/%d %H:%M:%S%.f",
+    "%d/%b/%Y:%H:%M:%S %z",
+    "%b %e %H:%M:%S",
+];
+
+/// Fraction of sampled lines a format must parse to be picked for a file
+const MIN_MATCH_SCORE: f64 = 0.5;
+
+/// Number of lines sampled from the start of a file to pick its format
+pub const SAMPLE_LINES: usize = 100;
+
+/// Alternative spellings of the well known fields in structured logs
+const FIELD_ALIASES: &[(&str, &[&str])] = &[
+    ("timestamp", &["time", "ts", "@timestamp", "datetime", "date", "t"]),
+    ("level", &["lvl", "severity", "loglevel", "log.level"]),
+    ("message", &["msg", "@message", "text", "log"]),
+    ("service", &["app", "application", "program", "logger", "component"]),
+];
+
+const SYSLOG_REGEX: &str = r"^(?P<timestamp>[A-Z][a-z]{2} [ \d]\d \d\d:\d\d:\d\d) (?P<host>\S+) (?P<service>[^:\[\s]+)(?:\[(?P<pid>\d+)\])?: (?P<message>.*)$";
+const APACHE_REGEX: &str = r#"^(?P<client>\S+) \S+ (?P<user>\S+) \[(?P<timestamp>[^\]]+)\] "(?P<method>[A-Z]+) (?P<path>\S+)(?: (?P<protocol>[^"]*))?" (?P<status>\d{3}) (?P<size>\S+)(?: "(?P<referer>[^"]*)" "(?P<agent>[^"]*)")?"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl Level {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+            Level::Fatal => "FATAL",
+        }
+    }
+
+    /// Parse the common spellings of a log level, ignoring case
+    pub fn parse(s: &str) -> Option<Level> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "trace" | "finest" | "finer" => Some(Level::Trace),
+            "debug" | "fine" | "dbg" => Some(Level::Debug),
+            "info" | "information" | "notice" => Some(Level::Info),
+            "warn" | "warning" => Some(Level::Warn),
+            "error" | "err" | "severe" => Some(Level::Error),
+            "fatal" | "critical" | "crit" | "alert" | "emerg" | "panic" => Some(Level::Fatal),
+            _ => None,
+        }
+    }
+}
+
+/// A single log line broken into named fields
+#[derive(Debug, Clone, Default)]
+pub struct Record {
+    pub fields: Vec<(String, String)>,
+    /// Byte range of each field's value in the line, in the order of `fields`
+    pub spans: Vec<Option<(usize, usize)>>,
+    pub timestamp: Option<NaiveDateTime>,
+    pub level: Option<Level>,
+}
+
+impl Record {
+    /// Look up a field by name, falling back on the usual aliases
+    /// (e.g. `message` also finds `msg`)
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.index(name).map(|i| self.fields[i].1.as_str())
+    }
+
+    /// Where the value of a field sits in the line, if the format could tell
+    pub fn span(&self, name: &str) -> Option<(usize, usize)> {
+        self.spans.get(self.index(name)?).copied().flatten()
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        let field = |name: &str| self.fields.iter().position(|(field, _)| field == name);
+        field(name).or_else(|| {
+            FIELD_ALIASES
+                .iter()
+                .find(|(canonical, _)| *canonical == name)
+                .and_then(|(_, aliases)| aliases.iter().find_map(|alias| field(alias)))
+        })
+    }
+}
+
+/// Format definition as written in a formats file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FormatSpec {
+    name: String,
+    regex: String,
+    #[serde(default)]
+    timestamp_format: Vec<String>,
+    #[serde(default)]
+    level_map: HashMap<String, String>,
+    start_pattern: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FormatsFile {
+    #[serde(default)]
+    format: Vec<FormatSpec>,
+}
+
+/// How a format splits a line into fields
+#[derive(Debug, Clone)]
+enum FormatKind {
+    Regex(Regex),
+    Json,
+    Logfmt,
+    Csv { headers: Vec<String> },
+}
+
+#[derive(Debug, Clone)]
+pub struct LogFormat {
+    name: String,
+    kind: FormatKind,
+    timestamp_formats: Vec<String>,
+    level_map: HashMap<String, Level>,
+    start_pattern: Option<Regex>,
+}
+
+impl LogFormat {
+    fn new(name: &str, kind: FormatKind) -> Self {
+        LogFormat {
+            name: name.to_string(),
+            kind,
+            timestamp_formats: Vec::new(),
+            level_map: HashMap::new(),
+            start_pattern: None,
+        }
+    }
+
+    fn from_spec(spec: FormatSpec) -> Result<Self> {
+        let regex = Regex::new(&spec.regex)
+            .with_context(|| format!("Invalid regex in format '{}'", spec.name))?;
+
+        let mut level_map = HashMap::new();
+        for (raw, level) in spec.level_map {
+            let level = Level::parse(&level).ok_or_else(|| {
+                anyhow::anyhow!("Unknown level '{}' in format '{}'", level, spec.name)
+            })?;
+            level_map.insert(raw, level);
+        }
+
+        let start_pattern = spec
+            .start_pattern
+            .map(|pattern| {
+                Regex::new(&pattern)
+                    .with_context(|| format!("Invalid start_pattern in format '{}'", spec.name))
+            })
+            .transpose()?;
+
+        Ok(LogFormat {
+            name: spec.name,
+            kind: FormatKind::Regex(regex),
+            timestamp_formats: spec.timestamp_format,
+            level_map,
+            start_pattern,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Pattern matching the first line of a multi-line record, if the format defines one
+    pub fn start_pattern(&self) -> Option<&Regex> {
+        self.start_pattern.as_ref()
+    }
+
+    /// Split a line into fields, or None if the line does not match this format
+    pub fn parse(&self, line: &str) -> Option<Record> {
+        let fields = match &self.kind {
+            FormatKind::Regex(regex) => {
+                let caps = regex.captures(line)?;
+                regex
+                    .capture_names()
+                    .flatten()
+                    .filter_map(|name| {
+                        caps.name(name).map(|m| {
+                            (name.to_string(), m.as_str().to_string(), Some((m.start(), m.end())))
+                        })
+                    })
+                    .collect()
+            }
+            FormatKind::Json => parse_json(line)?,
+            FormatKind::Logfmt => parse_logfmt(line)?,
+            FormatKind::Csv { headers } => {
+                let values = split_csv(line);
+                if values.len() != headers.len() {
+                    return None;
+                }
+                headers
+                    .iter()
+                    .cloned()
+                    .zip(values)
+                    .map(|(name, (value, span))| (name, value, Some(span)))
+                    .collect()
+            }
+        };
+
+        let (fields, spans) = fields
+            .into_iter()
+            .map(|(name, value, span)| ((name, value), span))
+            .unzip();
+        let mut record = Record {
+            fields,
+            spans,
+            timestamp: None,
+            level: None,
+        };
+        record.timestamp = record
+            .get("timestamp")
+            .and_then(|ts| self.parse_timestamp(ts));
+        record.level = record
+            .get("level")
+            .and_then(|level| self.map_level(level))
+            .or_else(|| record.get("status").and_then(level_from_status));
+        Some(record)
+    }
+
+    fn parse_timestamp(&self, ts: &str) -> Option<NaiveDateTime> {
+        if self.timestamp_formats.is_empty() {
+            parse_timestamp_with(ts, DEFAULT_TIMESTAMP_FORMATS.iter().copied())
+        } else {
+            parse_timestamp_with(ts, self.timestamp_formats.iter().map(String::as_str))
+        }
+    }
+
+    fn map_level(&self, raw: &str) -> Option<Level> {
+        self.level_map
+            .get(raw)
+            .copied()
+            .or_else(|| Level::parse(raw))
+    }
+}
+
+/// Derive a level from an HTTP status code (access logs have no level)
+fn level_from_status(status: &str) -> Option<Level> {
+    match status.parse::<u16>().ok()? {
+        500..=599 => Some(Level::Error),
+        400..=499 => Some(Level::Warn),
+        100..=399 => Some(Level::Info),
+        _ => None,
+    }
+}
+
+fn parse_timestamp_with<'a>(
+    ts: &str,
+    formats: impl Iterator<Item = &'a str>,
+) -> Option<NaiveDateTime> {
+    let ts = ts.trim();
+    for fmt in formats {
+        if let Ok(parsed) = NaiveDateTime::parse_from_str(ts, fmt) {
+            return Some(parsed);
+        }
+        if let Ok(parsed) = DateTime::parse_from_str(ts, fmt) {
+            return Some(parsed.naive_local());
+        }
+        // Layouts without a year (e.g. syslog) are assumed to be from this year
+        if !fmt.contains("%Y") && !fmt.contains("%y") {
+            let with_year = format!("{} {}", Local::now().year(), ts);
+            if let Ok(parsed) = NaiveDateTime::parse_from_str(&with_year, &format!("%Y {}", fmt)) {
+                return Some(parsed);
+            }
+        }
+    }
+    None
+}
+
+/// Field name, value, and byte range of the value in the line when known
+type ParsedField = (String, String, Option<(usize, usize)>);
+
+fn parse_json(line: &str) -> Option<Vec<ParsedField>> {
+    if !line.trim().starts_with('{') {
+        return None;
+    }
+    let value: serde_json::Value = serde_json::from_str(line.trim()).ok()?;
+    let mut fields = Vec::new();
+    flatten_json("", &value, &mut fields);
+    Some(
+        fields
+            .into_iter()
+            .map(|(name, value)| {
+                let span = json_span(line, &name, &value);
+                (name, value, span)
+            })
+            .collect(),
+    )
+}
+
+/// Where a JSON value sits in the line. serde_json keeps no offsets, so look
+/// for the value right after its key.
+fn json_span(line: &str, name: &str, value: &str) -> Option<(usize, usize)> {
+    let key = format!("\"{}\"", name.rsplit('.').next().unwrap_or(name));
+    let escaped = serde_json::to_string(value).ok()?;
+    let escaped = &escaped[1..escaped.len() - 1];
+
+    for (at, _) in line.match_indices(&key) {
+        let Some(rest) = line[at + key.len()..].trim_start().strip_prefix(':') else {
+            continue;
+        };
+        let rest = rest.trim_start();
+        let (start, raw) = match rest.strip_prefix('"') {
+            Some(string) => (line.len() - string.len(), escaped),
+            None => (line.len() - rest.len(), value),
+        };
+        if line[start..].starts_with(raw) {
+            return Some((start, start + raw.len()));
+        }
+    }
+    None
+}
+
+/// Flatten a JSON object into dotted field names
+fn flatten_json(prefix: &str, value: &serde_json::Value, fields: &mut Vec<(String, String)>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                let name = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten_json(&name, value, fields);
+            }
+        }
+        serde_json::Value::String(s) => fields.push((prefix.to_string(), s.clone())),
+        other => fields.push((prefix.to_string(), other.to_string())),
+    }
+}
+
+/// Parse `key=value key2="quoted value"` pairs; every token must be a pair
+fn parse_logfmt(line: &str) -> Option<Vec<ParsedField>> {
+    let mut fields = Vec::new();
+    let mut chars = line.char_indices().peekable();
+
+    while chars.peek().is_some_and(|(_, c)| c.is_whitespace()) {
+        chars.next();
+    }
+    while chars.peek().is_some() {
+        let mut key = String::new();
+        let mut has_value = false;
+        for (_, c) in chars.by_ref() {
+            if c == '=' {
+                has_value = true;
+                break;
+            }
+            if c.is_whitespace() {
+                return None;
+            }
+            key.push(c);
+        }
+        if key.is_empty() || !has_value {
+            return None;
+        }
+
+        let mut value = String::new();
+        let mut start = chars.peek().map_or(line.len(), |(i, _)| *i);
+        let mut end = start;
+        if chars.peek().is_some_and(|(_, c)| *c == '"') {
+            chars.next();
+            start += 1;
+            end = line.len();
+            let mut escaped = false;
+            for (i, c) in chars.by_ref() {
+                match c {
+                    '\\' if !escaped => escaped = true,
+                    '"' if !escaped => {
+                        end = i;
+                        break;
+                    }
+                    _ => {
+                        value.push(c);
+                        escaped = false;
+                    }
+                }
+            }
+        } else {
+            while let Some((i, c)) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                value.push(*c);
+                end = i + c.len_utf8();
+                chars.next();
+            }
+        }
+        fields.push((key, value, Some((start, end))));
+
+        while chars.peek().is_some_and(|(_, c)| c.is_whitespace()) {
+            chars.next();
+        }
+    }
+
+    // A single pair is too weak a signal; plain text often contains "a=b"
+    if fields.len() < 2 {
+        return None;
+    }
+    Some(fields)
+}
+
+/// Split a CSV line on commas, honouring double quoted values. Each value
+/// comes with its byte range in the line, inside any quotes.
+fn split_csv(line: &str) -> Vec<(String, (usize, usize))> {
+    let mut values = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut field_start = 0;
+    let mut chars = line.char_indices().peekable();
+
+    let span = |start: usize, end: usize| {
+        let raw = &line[start..end];
+        if raw.len() >= 2 && raw.starts_with('"') && raw.ends_with('"') {
+            (start + 1, end - 1)
+        } else {
+            (start, end)
+        }
+    };
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek().is_some_and(|(_, c)| *c == '"') => {
+                current.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                values.push((std::mem::take(&mut current), span(field_start, i)));
+                field_start = i + 1;
+            }
+            _ => current.push(c),
+        }
+    }
+    values.push((current, span(field_start, line.len())));
+    values
+}
+
+/// Build a CSV format from the sample, using the first line as the header when it looks like one
+fn csv_format(sample: &[&str]) -> Option<LogFormat> {
+    let first: Vec<String> = split_csv(sample.first()?).into_iter().map(|(value, _)| value).collect();
+    if first.len() < 3 {
+        return None;
+    }
+
+    let is_header = first.iter().all(|name| {
+        let name = name.trim();
+        !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || "_-. ".contains(c))
+            && !name.chars().all(|c| c.is_ascii_digit())
+    });
+    let headers = if is_header {
+        first.iter().map(|name| name.trim().to_lowercase()).collect()
+    } else {
+        (1..=first.len()).map(|i| format!("col{}", i)).collect()
+    };
+
+    Some(LogFormat::new("csv", FormatKind::Csv { headers }))
+}
+
+/// Formats that ship with the viewer. CSV needs the sample to learn its columns.
+pub fn builtin_formats(sample: &[&str]) -> Vec<LogFormat> {
+    let mut formats = vec![
+        LogFormat::new("json", FormatKind::Json),
+        LogFormat::new("logfmt", FormatKind::Logfmt),
+        LogFormat::new(
+            "syslog",
+            FormatKind::Regex(Regex::new(SYSLOG_REGEX).expect("valid syslog regex")),
+        ),
+        LogFormat::new(
+            "apache",
+            FormatKind::Regex(Regex::new(APACHE_REGEX).expect("valid apache regex")),
+        ),
+    ];
+    formats.extend(csv_format(sample));
+    formats
+}
+
+/// User formats followed by the built-in ones, in detection priority order
+pub fn all_formats(user_formats: &[LogFormat], sample: &[&str]) -> Vec<LogFormat> {
+    let mut formats = user_formats.to_vec();
+    formats.extend(builtin_formats(sample));
+    formats
+}
+
+/// Load format definitions from a TOML file, or from every .toml file in a directory
+pub fn load_formats(path: &Path) -> Result<Vec<LogFormat>> {
+    if path.is_dir() {
+        let mut entries: Vec<_> = fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+            .collect();
+        entries.sort();
+
+        let mut formats = Vec::new();
+        for entry in entries {
+            formats.extend(load_formats(&entry)?);
+        }
+        return Ok(formats);
+    }
+
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read formats file {}", path.display()))?;
+    let file: FormatsFile = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse formats file {}", path.display()))?;
+
+    file.format.into_iter().map(LogFormat::from_spec).collect()
+}
+
+/// Score a format on the sample: the share of lines it parses, with a small
+/// bonus for lines that also yield a timestamp or level so that specific
+/// formats beat catch-all ones.
+fn score(format: &LogFormat, sample: &[&str]) -> f64 {
+    let mut matched = 0;
+    let mut structured = 0;
+    for line in sample {
+        if let Some(record) = format.parse(line) {
+            matched += 1;
+            if record.timestamp.is_some() || record.level.is_some() {
+                structured += 1;
+            }
+        }
+    }
+    let total = sample.len() as f64;
+    matched as f64 / total + 0.1 * structured as f64 / total
+}
+
+/// Pick the format that best describes the sample lines
+pub fn best_match<'a>(formats: &'a [LogFormat], sample: &[&str]) -> Option<&'a LogFormat> {
+    let non_empty: Vec<&str> = sample
+        .iter()
+        .copied()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    if non_empty.is_empty() {
+        return None;
+    }
+
+    let mut best: Option<(&LogFormat, f64)> = None;
+    for format in formats {
+        let score = score(format, &non_empty);
+        debug!("Format '{}' scored {:.2}", format.name(), score);
+        if score >= MIN_MATCH_SCORE && best.is_none_or(|(_, best_score)| score > best_score) {
+            best = Some((format, score));
+        }
+    }
+    best.map(|(format, _)| format)
+}
+
+/// Detect the format of a file from its first lines, trying user formats first
+pub fn detect(user_formats: &[LogFormat], sample: &[&str]) -> Option<LogFormat> {
+    best_match(&all_formats(user_formats, sample), sample).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formats(toml: &str) -> Vec<LogFormat> {
+        let file: FormatsFile = toml::from_str(toml).unwrap();
+        file.format
+            .into_iter()
+            .map(|spec| LogFormat::from_spec(spec).unwrap())
+            .collect()
+    }
+
+    const APP_FORMAT: &str = r#"
+[[format]]
+name = "app"
+regex = '^(?P<timestamp>\d{4}-\d\d-\d\d \d\d:\d\d:\d\d) \[(?P<thread>[^\]]+)\] (?P<level>\w+) (?P<logger>\S+) - (?P<message>.*)$'
+timestamp_format = ["%Y-%m-%d %H:%M:%S"]
+
+[format.level_map]
+W = "warn"
+"#;
+
+    #[test]
+    fn test_parse_named_captures() {
+        let formats = formats(APP_FORMAT);
+        let line = "2024-01-02 03:04:05 [W-pool] W com.example.App - disk almost full";
+        let record = formats[0].parse(line).unwrap();
+
+        assert_eq!(record.get("thread"), Some("W-pool"));
+        assert_eq!(record.span("level"), Some((29, 30)));
+        assert_eq!(
+            crate::matcher::Matcher::parse("@level=warn").spans(line, Some(&formats[0])),
+            vec![(29, 30)]
+        );
+        assert_eq!(record.get("logger"), Some("com.example.App"));
+        assert_eq!(record.get("message"), Some("disk almost full"));
+        assert_eq!(record.level, Some(Level::Warn));
+        assert_eq!(
+            record.timestamp.unwrap().to_string(),
+            "2024-01-02 03:04:05"
+        );
+        assert!(formats[0].parse("not a log line").is_none());
+    }
+
+    #[test]
+    fn test_best_match() {
+        let formats = formats(&format!(
+            "{}\n[[format]]\nname = \"kv\"\nregex = '^(?P<key>\\w+)=(?P<value>.*)$'\n",
+            APP_FORMAT
+        ));
+
+        let sample = [
+            "2024-01-02 03:04:05 [main] INFO a.B - started",
+            "2024-01-02 03:04:06 [main] INFO a.B - running",
+            "a=b",
+        ];
+        assert_eq!(best_match(&formats, &sample).unwrap().name(), "app");
+        assert!(best_match(&formats, &["plain text"]).is_none());
+    }
+
+    #[test]
+    fn test_detect_builtin_formats() {
+        let json = [r#"{"ts":"2024-01-02T03:04:05Z","level":"error","msg":"boom"}"#];
+        let record = detect(&[], &json).unwrap();
+        assert_eq!(record.name(), "json");
+        let parsed = record.parse(json[0]).unwrap();
+        assert_eq!(parsed.get("message"), Some("boom"));
+        assert_eq!(parsed.level, Some(Level::Error));
+        assert!(parsed.timestamp.is_some());
+
+        let logfmt = [r#"time=2024-01-02T03:04:05Z level=info msg="user logged in" user=bob"#];
+        assert_eq!(detect(&[], &logfmt).unwrap().name(), "logfmt");
+
+        let syslog = ["Jan  2 03:04:05 host sshd[123]: Accepted publickey for bob"];
+        let format = detect(&[], &syslog).unwrap();
+        assert_eq!(format.name(), "syslog");
+        assert_eq!(format.parse(syslog[0]).unwrap().get("service"), Some("sshd"));
+
+        let apache = [r#"127.0.0.1 - - [02/Jan/2024:03:04:05 +0000] "GET /index.html HTTP/1.1" 503 512 "-" "curl/8.0""#];
+        let format = detect(&[], &apache).unwrap();
+        assert_eq!(format.name(), "apache");
+        assert_eq!(format.parse(apache[0]).unwrap().level, Some(Level::Error));
+
+        let csv = ["time,level,service,message", "2024-01-02 03:04:05,INFO,api,started"];
+        let format = detect(&[], &csv).unwrap();
+        assert_eq!(format.name(), "csv");
+        assert_eq!(format.parse(csv[1]).unwrap().get("service"), Some("api"));
+
+        assert!(detect(&[], &["just some text", "more text"]).is_none());
+    }
+
+    #[test]
+    fn test_unknown_keys_rejected() {
+        let result: Result<FormatsFile, _> =
+            toml::from_str("[[format]]\nname = \"x\"\nregex = \"x\"\ncolour = \"red\"\n");
+        assert!(result.is_err());
+    }
+}
@@ -5,61 +5,209 @@ use crossterm::{
     terminal::*,
     QueueableCommand,
 };
-use log::debug;
+use std::collections::HashMap;
 use std::io::{stdout, Write};
 
-use crate::log_file::Line;
+use crate::config;
+use crate::diff::{DiffKind, DiffRow};
+use crate::line_editor::LineEditor;
+use crate::log_file::{Line, LogFile, SearchDirection};
+use crate::log_format::{Level, LogFormat};
+use crate::matcher::Matcher;
+use crate::message::Message;
+use crate::overlay::ListOverlay;
+use crate::timeline::{Timeline, GROUP_ERROR, GROUP_OTHER, GROUP_WARN, LEVEL_GROUPS};
+
+/// Narrowest the message column gets in table view, even on small terminals
+const MIN_MESSAGE_WIDTH: usize = 10;
+
+/// Width of the marker column in front of each line: the marker and a space
+const GUTTER_WIDTH: usize = 2;
+
+/// Height of the timeline panel, including its axis row
+pub const TIMELINE_ROWS: usize = 8;
+
+/// Colors given to highlights without an explicit color, last one first
+/// (unless the config sets a palette)
+const HIGHLIGHT_COLORS: [Color; 9] = [
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::DarkYellow,
+    Color::DarkCyan,
+    Color::DarkGreen,
+];
+
+/// Marker drawn in the gutter of a line (anomalies, marks, notes...)
+pub type GutterMarker = (char, Color);
+
+/// A column of the table view. The `message` column always takes the
+/// remaining width and wraps; the others are cut to `width`.
+struct Column {
+    field: String,
+    width: usize,
+    visible: bool,
+}
+
+impl Column {
+    fn new(field: &str, width: usize) -> Self {
+        Column {
+            field: field.to_string(),
+            width,
+            visible: true,
+        }
+    }
+}
 
 pub struct LogViewer {
     stdout: std::io::Stdout,
     cursor_position: (u16, u16),
     pub search_pattern: Option<String>,
+    /// Direction of the last `/` or `?` search, which `n` keeps going in
+    pub search_direction: SearchDirection,
     search_color: Color,
+    palette: Vec<Color>,
     unused_colors: Vec<Color>,
-    highlight: Vec<(String, Color)>,
+    highlight: Vec<(Matcher, Color)>,
+    table_view: bool,
+    columns: Vec<Column>,
+    gutter: Option<HashMap<usize, GutterMarker>>,
+    panel_rows: usize,
+    wrap_limit: usize,
+    message: Option<Message>,
 }
 
 impl LogViewer {
     pub fn new() -> Self {
-        let unused_colors = vec![
-            Color::Red,
-            Color::Green,
-            Color::Yellow,
-            Color::Blue,
-            Color::Magenta,
-            Color::Cyan,
-            Color::DarkYellow,   
-            Color::DarkCyan,
-            Color::DarkGreen
-        ];
-
         LogViewer {
             stdout: stdout(),
             cursor_position: (0, 0),
             search_pattern: None,
+            search_direction: SearchDirection::Forward,
             search_color: Color::Red,
-            unused_colors,
+            palette: HIGHLIGHT_COLORS.to_vec(),
+            unused_colors: HIGHLIGHT_COLORS.to_vec(),
             highlight: Vec::new(),
+            table_view: false,
+            columns: vec![
+                Column::new("timestamp", 23),
+                Column::new("level", 5),
+                Column::new("service", 12),
+                Column::new("message", 0),
+            ],
+            gutter: None,
+            panel_rows: 0,
+            wrap_limit: config::DEFAULT_WRAP_LIMIT,
+            message: None,
         }
     }
 
+    /// Reserve rows below the log lines for a panel (0 to remove it)
+    pub fn set_panel_rows(&mut self, rows: usize) {
+        self.panel_rows = rows;
+    }
+
+    /// Markers for the lines about to be drawn, keyed by line number. With
+    /// `None` no gutter column is drawn.
+    pub fn set_gutter(&mut self, markers: Option<HashMap<usize, GutterMarker>>) {
+        self.gutter = markers;
+    }
+
+    fn gutter_width(&self) -> usize {
+        if self.gutter.is_some() {
+            GUTTER_WIDTH
+        } else {
+            0
+        }
+    }
+
+    /// Print the gutter cell of a line; continuation rows get a blank cell
+    fn print_gutter(&mut self, line_number: Option<usize>) -> Result<()> {
+        let Some(gutter) = &self.gutter else {
+            return Ok(());
+        };
+
+        match line_number.and_then(|n| gutter.get(&n)) {
+            Some((marker, color)) => {
+                self.stdout.queue(Print(format!("{} ", marker).with(*color)))?;
+            }
+            None => {
+                self.stdout.queue(Print(" ".repeat(GUTTER_WIDTH)))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn toggle_table_view(&mut self) {
+        self.table_view = !self.table_view;
+    }
+
+    /// Show or hide a table column; showing an unknown field adds a column for it
+    pub fn set_column_visible(&mut self, field: &str, visible: bool) -> Result<()> {
+        if let Some(column) = self.columns.iter_mut().find(|c| c.field == field) {
+            column.visible = visible;
+            Ok(())
+        } else if visible {
+            // New columns go before the message column
+            let pos = self.columns.len().saturating_sub(1);
+            self.columns.insert(pos, Column::new(field, 12));
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("No such column: {}", field))
+        }
+    }
+
+    pub fn set_column_width(&mut self, field: &str, width: usize) -> Result<()> {
+        if field == "message" {
+            return Err(anyhow::anyhow!("The message column takes the remaining width"));
+        }
+        let column = self
+            .columns
+            .iter_mut()
+            .find(|c| c.field == field)
+            .ok_or_else(|| anyhow::anyhow!("No such column: {}", field))?;
+        column.width = width.max(1);
+        Ok(())
+    }
+
     pub fn clear(&mut self) -> Result<()> {
         self.stdout.queue(Clear(ClearType::All))?;
         Ok(())
     }
 
     pub fn get_row_cols(&self) -> Result<(usize, usize)> {
+        let (rows, cols) = self.get_screen_row_cols()?;
+        // Save rows for the panel, if one is shown
+        Ok((rows.saturating_sub(self.panel_rows).max(1), cols))
+    }
+
+    /// Rows above the status bar, including any panel
+    fn get_screen_row_cols(&self) -> Result<(usize, usize)> {
         let size = window_size()?;
-        // Save 1 row for the input bar
-        Ok((size.rows as usize - 1, size.columns as usize))
+        // Save 1 row for the status bar and 1 for the input bar
+        Ok((size.rows as usize - 2, size.columns as usize))
     }
 
-    pub fn set_search_color(&mut self, color: &str) {
-        if let Ok(color) = Color::try_from(color) {
-            self.search_color = color;
-        } else {
-            debug!("Invalid color string: {}", color);
-        }
+    pub fn set_search_color(&mut self, color: &str) -> Result<()> {
+        self.search_color =
+            Color::try_from(color).map_err(|_| anyhow::anyhow!("Unknown color '{}'", color))?;
+        Ok(())
+    }
+
+    pub fn set_message(&mut self, message: Message) {
+        self.message = Some(message);
+    }
+
+    pub fn message(&self) -> Option<&Message> {
+        self.message.as_ref()
+    }
+
+    /// Drop the message, returning whether there was one on screen
+    pub fn clear_message(&mut self) -> bool {
+        self.message.take().is_some()
     }
 
     pub fn set_cursor(&mut self, x: u16, y: u16) -> Result<()> {
@@ -76,47 +224,277 @@ impl LogViewer {
         Ok(())
     }
 
-    pub fn set_cursor_to_command_line(&mut self) -> Result<()> {
-        let (rows, _) = self.get_row_cols()?;
+    pub fn clear_command_line(&mut self) -> Result<()> {
+        let (rows, _) = self.get_screen_row_cols()?;
+        self.stdout.queue(cursor::MoveTo(0, rows as u16 + 1))?;
+        self.stdout.queue(Clear(ClearType::CurrentLine))?;
+        self.stdout.flush()?;
+        Ok(())
+    }
 
-        debug!("Setting cursor to command line at row: {}", rows);
+    /// Draw the command prompt input and put the terminal cursor at its cursor
+    pub fn print_prompt(&mut self, editor: &mut LineEditor) -> Result<()> {
+        let (rows, cols) = self.get_screen_row_cols()?;
+        let (text, cursor_col) = editor.view(cols);
 
-        self.stdout.queue(cursor::MoveTo(0, rows as u16))?;
+        self.stdout.queue(cursor::MoveTo(0, rows as u16 + 1))?;
+        self.stdout.queue(Clear(ClearType::CurrentLine))?;
+        self.stdout.queue(Print(text))?;
+        self.stdout.queue(cursor::MoveTo(cursor_col as u16, rows as u16 + 1))?;
         self.stdout.flush()?;
         Ok(())
     }
 
-    pub fn clear_command_line(&mut self) -> Result<()> {
-        let (rows, _) = self.get_row_cols()?;
+    /// List completion candidates on the status bar row, the selected one in
+    /// reverse video, showing the part of the list that holds the selection
+    pub fn print_completions(&mut self, candidates: &[String], selected: Option<usize>) -> Result<()> {
+        let (rows, cols) = self.get_screen_row_cols()?;
+
+        // Split the candidates into pages that fit on the row
+        let mut pages = Vec::new();
+        let (mut start, mut width) = (0, 0);
+        for (i, candidate) in candidates.iter().enumerate() {
+            let len = candidate.chars().count() + 2;
+            if i > start && width + len > cols {
+                pages.push(start..i);
+                start = i;
+                width = 0;
+            }
+            width += len;
+        }
+        pages.push(start..candidates.len());
+        let page = pages
+            .into_iter()
+            .find(|page| page.contains(&selected.unwrap_or(0)))
+            .unwrap_or_default();
+
         self.stdout.queue(cursor::MoveTo(0, rows as u16))?;
         self.stdout.queue(Clear(ClearType::CurrentLine))?;
+        let mut remaining = cols;
+        for i in page {
+            let text: String = candidates[i].chars().take(remaining).collect();
+            remaining = remaining.saturating_sub(text.chars().count() + 2);
+            if Some(i) == selected {
+                self.stdout.queue(Print(text.reverse()))?;
+            } else {
+                self.stdout.queue(Print(text))?;
+            }
+            if remaining == 0 {
+                break;
+            }
+            self.stdout.queue(Print("  "))?;
+        }
+        self.stdout.flush()?;
+        Ok(())
+    }
+
+    /// Draw the message, if any, on the command line row
+    pub fn print_message(&mut self) -> Result<()> {
+        let Some(message) = &self.message else {
+            return Ok(());
+        };
+        let (rows, cols) = self.get_screen_row_cols()?;
+        let text: String = message.text.chars().take(cols).collect();
+        let color = message.color();
+
+        self.stdout.queue(cursor::MoveTo(0, rows as u16 + 1))?;
+        self.stdout.queue(Clear(ClearType::CurrentLine))?;
+        match color {
+            Some(color) => self.stdout.queue(Print(text.with(color)))?,
+            None => self.stdout.queue(Print(text))?,
+        };
+        self.stdout.queue(cursor::MoveTo(
+            self.cursor_position.0,
+            self.cursor_position.1,
+        ))?;
+        self.stdout.flush()?;
+        Ok(())
+    }
+
+    /// Draw the status bar in reverse video on the row above the command line
+    pub fn print_status(&mut self, text: &str) -> Result<()> {
+        let (rows, cols) = self.get_screen_row_cols()?;
+        let status: String = format!("{:<width$}", text, width = cols)
+            .chars()
+            .take(cols)
+            .collect();
+
+        self.stdout.queue(cursor::MoveTo(0, rows as u16))?;
+        self.stdout.queue(Print(status.reverse()))?;
+        self.stdout.queue(cursor::MoveTo(
+            self.cursor_position.0,
+            self.cursor_position.1,
+        ))?;
         self.stdout.flush()?;
         Ok(())
     }
 
-    pub fn print_line_with_highlight(&mut self, line_str: &str) -> Result<()> {
+    /// Rows available for overlay entries below the title and header
+    pub fn overlay_height(&self) -> Result<usize> {
+        let (rows, _) = self.get_screen_row_cols()?;
+        Ok(rows.saturating_sub(2))
+    }
+
+    /// Draw a list overlay over the log view, with the selected row in reverse video
+    pub fn print_overlay(&mut self, overlay: &ListOverlay) -> Result<()> {
+        let (_, cols) = self.get_screen_row_cols()?;
+        let height = self.overlay_height()?;
+
+        self.stdout.queue(Clear(ClearType::All))?;
+        self.stdout.queue(cursor::MoveTo(0, 0))?;
+        self.stdout.queue(Print(fit(&overlay.title, cols).reverse()))?;
+        self.stdout.queue(Print("\r\n"))?;
+        self.stdout.queue(Print(fit(&overlay.header, cols).bold()))?;
+        self.stdout.queue(Print("\r\n"))?;
+
+        for (i, row) in overlay
+            .rows
+            .iter()
+            .enumerate()
+            .skip(overlay.scroll)
+            .take(height)
+        {
+            let text = fit(row, cols);
+            if i == overlay.selected {
+                self.stdout.queue(Print(text.reverse()))?;
+            } else {
+                self.stdout.queue(Print(text))?;
+            }
+            self.stdout.queue(Print("\r\n"))?;
+        }
+
+        self.print_status(&overlay.footer)
+    }
+
+    /// Draw two files side by side from row `top` of their alignment.
+    /// Lines only on the left are red, lines only on the right green.
+    pub fn print_diff(
+        &mut self,
+        rows: &[DiffRow],
+        top: usize,
+        left: &LogFile,
+        right: &LogFile,
+    ) -> Result<()> {
+        let (height, cols) = self.get_screen_row_cols()?;
+        let pane_width = cols.saturating_sub(1) / 2;
+
+        self.stdout.queue(Clear(ClearType::All))?;
+        self.stdout.queue(cursor::MoveTo(0, 0))?;
+
+        let cell = |lf: &LogFile, line: Option<usize>| -> String {
+            match line {
+                Some(n) => fit(
+                    &format!("{:>6} {}", n + 1, lf.get_line(n).unwrap_or_default()),
+                    pane_width,
+                ),
+                None => " ".repeat(pane_width),
+            }
+        };
+
+        for row in rows.iter().skip(top).take(height) {
+            let left_cell = cell(left, row.left);
+            let right_cell = cell(right, row.right);
+
+            match row.kind {
+                DiffKind::Equal => self.stdout.queue(Print(left_cell))?,
+                DiffKind::Removed => self.stdout.queue(Print(left_cell.red()))?,
+                DiffKind::Added => self.stdout.queue(Print(left_cell))?,
+            };
+            self.stdout.queue(Print("│".dark_grey()))?;
+            match row.kind {
+                DiffKind::Added => self.stdout.queue(Print(right_cell.green()))?,
+                _ => self.stdout.queue(Print(right_cell))?,
+            };
+            self.stdout.queue(Print("\r\n"))?;
+        }
+
+        self.stdout.flush()?;
+        Ok(())
+    }
+
+    /// Draw the timeline panel below the log lines: one bar per bucket,
+    /// split by level when stacked, with the selected bucket's time below
+    pub fn print_timeline(&mut self, timeline: &Timeline) -> Result<()> {
+        let (top, cols) = self.get_row_cols()?;
+        let bar_rows = self.panel_rows.saturating_sub(1);
+        let max = timeline
+            .buckets
+            .iter()
+            .map(|b| b.total())
+            .max()
+            .unwrap_or(0)
+            .max(1);
+
+        for row in 0..bar_rows {
+            // Rows count down from the top of the panel; level 1 is the bottom row
+            let level = bar_rows - row;
+            self.stdout.queue(cursor::MoveTo(0, (top + row) as u16))?;
+            self.stdout.queue(Clear(ClearType::CurrentLine))?;
+
+            for (i, bucket) in timeline.buckets.iter().enumerate().take(cols) {
+                // Bar height in eighths of a row
+                let eighths = bucket.total() * bar_rows * 8 / max;
+                let filled = eighths.saturating_sub((level - 1) * 8).min(8);
+                let glyph = match filled {
+                    0 => ' ',
+                    n => ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'][n - 1],
+                };
+
+                let color = if i == timeline.selected {
+                    Color::Cyan
+                } else if timeline.stacked {
+                    stack_color(bucket.counts, max, level, bar_rows)
+                } else {
+                    Color::Grey
+                };
+
+                if i == timeline.selected && glyph == ' ' {
+                    self.stdout.queue(Print("·".with(color)))?;
+                } else {
+                    self.stdout.queue(Print(glyph.with(color)))?;
+                }
+            }
+        }
+
+        let selected = timeline.bucket_start(timeline.selected);
+        let axis = format!(
+            "{}  |  {} ({} lines)  |  bucket {}s",
+            timeline.start.format("%Y-%m-%d %H:%M:%S"),
+            selected.format("%H:%M:%S"),
+            timeline
+                .buckets
+                .get(timeline.selected)
+                .map_or(0, |b| b.total()),
+            timeline.bucket_size.num_seconds()
+        );
+        self.stdout.queue(cursor::MoveTo(0, (top + bar_rows) as u16))?;
+        self.stdout.queue(Print(fit(&axis, cols).dark_grey()))?;
+
+        self.stdout.queue(cursor::MoveTo(
+            self.cursor_position.0,
+            self.cursor_position.1,
+        ))?;
+        self.stdout.flush()?;
+        Ok(())
+    }
+
+    pub fn print_line_with_highlight(
+        &mut self,
+        line_str: &str,
+        format: Option<&LogFormat>,
+    ) -> Result<()> {
         // Collect foreground matches
         let mut fg_matches = Vec::new();
-        for (pattern, color) in &self.highlight {
-            let mut search_start = 0;
-            while let Some(start) = line_str[search_start..].find(pattern) {
-                let abs_start = search_start + start;
-                let abs_end = abs_start + pattern.len();
-                fg_matches.push((abs_start, abs_end, *color));
-                search_start = abs_end;
+        for (matcher, color) in &self.highlight {
+            for (start, end) in matcher.spans(line_str, format) {
+                fg_matches.push((start, end, *color));
             }
         }
 
         // Collect background matches (search pattern)
         let mut bg_matches = Vec::new();
         if let Some(pattern) = &self.search_pattern {
-            let mut search_start = 0;
-            while let Some(start) = line_str[search_start..].find(pattern) {
-                let abs_start = search_start + start;
-                let abs_end = abs_start + pattern.len();
-                bg_matches.push((abs_start, abs_end));
-                search_start = abs_end;
-            }
+            bg_matches = Matcher::parse(pattern).spans(line_str, format);
         }
 
         // Sort both by position
@@ -158,7 +536,7 @@ impl LogViewer {
                 .find(|(start, end, _)| start_pos >= *start && start_pos < *end);
 
             // Apply styling
-            if let Some(_) = current_bg {
+            if current_bg.is_some() {
                 self.stdout.queue(SetBackgroundColor(Color::Red))?;
             }
             if let Some((_, _, color)) = current_fg {
@@ -177,30 +555,49 @@ impl LogViewer {
         Ok(())
     }
 
-    pub fn print_screen(&mut self, lines: &[Line]) -> Result<Vec<usize>> {
+    pub fn print_screen(
+        &mut self,
+        lines: &[Line],
+        format: Option<&LogFormat>,
+    ) -> Result<Vec<usize>> {
+        if self.table_view {
+            if let Some(format) = format {
+                return self.print_table(lines, format);
+            }
+        }
+
         let (mut rows, cols) = self.get_row_cols()?;
         self.stdout.queue(cursor::MoveTo(0, 0))?;
 
         let mut line_numbers: Vec<usize> = Vec::new();
 
         for line in lines.iter() {
-            let line_len = line.data.len();
-
-            let mut num_lines_to_print = if line_len == 0 {
-	        1
-	    } else {
-	        line_len / cols + if line_len % cols > 0 { 1 } else { 0 }
-            };
-            num_lines_to_print = num_lines_to_print.min(3).min(rows);
+            let badge = fold_badge(line.folded);
+            // Gutter and badge take room in front of the text
+            let prefix_len = badge.chars().count() + self.gutter_width();
+            let line_len = line.data.len() + prefix_len;
+            let num_lines_to_print = self.line_rows(line.data, line.folded, None, cols).min(rows);
 	    
+            self.print_gutter(Some(line.line_number))?;
+            if !badge.is_empty() {
+                self.stdout.queue(Print(badge.as_str().magenta()))?;
+            }
+
             if line_len > num_lines_to_print * cols {
                 // Truncate long lines
-                let end_pos = num_lines_to_print * cols - 5; // Reserve space for "..."
-                self.print_line_with_highlight(&line.data[..end_pos])?;
+                // Reserve space for "[...]"; narrow terminals may leave no room for text
+                let end_pos = (num_lines_to_print * cols)
+                    .saturating_sub(5 + prefix_len)
+                    .min(line.data.len());
+                let end_pos = (0..=end_pos)
+                    .rev()
+                    .find(|&i| line.data.is_char_boundary(i))
+                    .unwrap_or(0);
+                self.print_line_with_highlight(&line.data[..end_pos], format)?;
                 //self.stdout.queue(Print(&line.data[..end_pos]))?;
                 self.stdout.queue(Print("[...]\r\n".red()))?;
             } else {
-                self.print_line_with_highlight(&line.data)?;
+                self.print_line_with_highlight(line.data, format)?;
                 self.stdout.queue(Print("\r\n"))?;
             }
 
@@ -224,22 +621,292 @@ impl LogViewer {
         Ok(line_numbers)
     }
 
-    pub fn set_highlight(&mut self, pattern: String, color_str: Option<String>) -> Result<()> {
+    /// Screen rows a line takes when drawn `cols` wide. With a format, the
+    /// table view counts the rows of the wrapped message column.
+    pub fn line_rows(&self, data: &str, folded: usize, format: Option<&LogFormat>, cols: usize) -> usize {
+        let badge_len = fold_badge(folded).chars().count();
+        let rows = match format.filter(|_| self.table_view) {
+            Some(format) => {
+                let (_, show_message, message_width) = self.table_layout(cols);
+                let message_len = match format.parse(data) {
+                    Some(record) => record.get("message").unwrap_or_default().chars().count(),
+                    None => data.chars().count(),
+                };
+                if show_message {
+                    (badge_len + message_len).div_ceil(message_width)
+                } else {
+                    1
+                }
+            }
+            None => (data.len() + badge_len + self.gutter_width()).div_ceil(cols.max(1)),
+        };
+        rows.clamp(1, self.wrap_limit)
+    }
+
+    /// Fixed columns with their widths, whether the message column is shown,
+    /// and the width left for it
+    fn table_layout(&self, cols: usize) -> (Vec<(String, usize)>, bool, usize) {
+        let fixed: Vec<(String, usize)> = self
+            .columns
+            .iter()
+            .filter(|c| c.visible && c.field != "message")
+            .map(|c| (c.field.clone(), c.width))
+            .collect();
+        let show_message = self
+            .columns
+            .iter()
+            .any(|c| c.visible && c.field == "message");
+        let fixed_width: usize =
+            fixed.iter().map(|(_, width)| width + 1).sum::<usize>() + self.gutter_width();
+        let message_width = cols.saturating_sub(fixed_width).max(MIN_MESSAGE_WIDTH);
+        (fixed, show_message, message_width)
+    }
+
+    /// Draw parsed lines as aligned columns. Lines the format cannot parse are
+    /// shown whole in the message column.
+    fn print_table(&mut self, lines: &[Line], format: &LogFormat) -> Result<Vec<usize>> {
+        let (mut rows, cols) = self.get_row_cols()?;
+        self.stdout.queue(cursor::MoveTo(0, 0))?;
+
+        let (fixed, show_message, message_width) = self.table_layout(cols);
+
+        let mut line_numbers: Vec<usize> = Vec::new();
+
+        for line in lines.iter() {
+            let record = format.parse(line.data);
+
+            let message = match &record {
+                Some(record) => record.get("message").unwrap_or_default(),
+                None => line.data,
+            };
+            let message: Vec<char> = if show_message {
+                fold_badge(line.folded).chars().chain(message.chars()).collect()
+            } else {
+                Vec::new()
+            };
+
+            let mut chunks: Vec<String> = message
+                .chunks(message_width)
+                .map(|chunk| chunk.iter().collect())
+                .collect();
+            if chunks.is_empty() {
+                chunks.push(String::new());
+            }
+            let num_lines_to_print = chunks.len().min(self.wrap_limit).min(rows);
+            let truncated = chunks.len() > num_lines_to_print;
+            chunks.truncate(num_lines_to_print);
+
+            for (row, chunk) in chunks.iter().enumerate() {
+                self.print_gutter(if row == 0 { Some(line.line_number) } else { None })?;
+                for (field, width) in &fixed {
+                    let value = if row == 0 {
+                        record.as_ref().and_then(|r| r.get(field)).unwrap_or_default()
+                    } else {
+                        ""
+                    };
+                    let cell = fit(value, *width);
+
+                    match record.as_ref().and_then(|r| r.level) {
+                        Some(level) if field == "level" && row == 0 => {
+                            self.stdout.queue(Print(cell.with(level_color(level))))?;
+                        }
+                        _ => {
+                            self.stdout.queue(Print(cell))?;
+                        }
+                    }
+                    self.stdout.queue(Print(" "))?;
+                }
+
+                if truncated && row + 1 == num_lines_to_print {
+                    let keep: String = chunk
+                        .chars()
+                        .take(message_width.saturating_sub(5))
+                        .collect();
+                    self.print_line_with_highlight(&keep, None)?;
+                    self.stdout.queue(Print("[...]".red()))?;
+                } else {
+                    self.print_line_with_highlight(chunk, None)?;
+                }
+                self.stdout.queue(Print("\r\n"))?;
+                line_numbers.push(line.line_number);
+            }
+
+            if num_lines_to_print >= rows {
+                break;
+            }
+
+            rows -= num_lines_to_print;
+        }
+
+        self.stdout.queue(cursor::MoveTo(
+            self.cursor_position.0,
+            self.cursor_position.1,
+        ))?;
+        self.stdout.flush()?;
+
+        Ok(line_numbers)
+    }
+
+    /// Highlight patterns with the names of their colors
+    pub fn highlights(&self) -> Vec<(String, String)> {
+        self.highlight
+            .iter()
+            .filter_map(|(matcher, color)| Some((matcher.to_string(), color_name(*color)?)))
+            .collect()
+    }
+
+    pub fn clear_highlights(&mut self) {
+        self.highlight.clear();
+        self.unused_colors = self.palette.clone();
+    }
+
+    /// Colors for highlights without an explicit color, first one first
+    pub fn set_palette(&mut self, colors: &[Color]) {
+        self.palette = colors.iter().rev().copied().collect();
+        self.unused_colors = self.palette.clone();
+        self.unused_colors.retain(|c| !self.highlight.iter().any(|(_, used)| used == c));
+    }
+
+    /// Most screen rows a long line wraps onto
+    pub fn set_wrap_limit(&mut self, rows: usize) {
+        self.wrap_limit = rows.max(1);
+    }
+
+    /// Highlight `pattern`, in the next unused color when none is given.
+    /// Returns the color used.
+    pub fn set_highlight(&mut self, pattern: String, color_str: Option<String>) -> Result<Color> {
+        let pattern = Matcher::parse(&pattern);
         if let Some(color_str) = color_str {
             if let Ok(color) = Color::try_from(color_str.as_str()) {
                 self.highlight.push((pattern, color));
                 self.unused_colors.retain(|c| *c != color);
-                Ok(())
+                Ok(color)
             } else {
-                Err(anyhow::anyhow!("Invalid color string: {}", color_str))
+                Err(anyhow::anyhow!("Unknown color '{}'", color_str))
             }
         } else {
             if let Some(color) = self.unused_colors.pop() {
                 self.highlight.push((pattern, color));
-                Ok(())
+                Ok(color)
             } else {
-                Err(anyhow::anyhow!("No unused colors available"))
+                Err(anyhow::anyhow!("Out of highlight colors, give one explicitly"))
+            }
+        }
+    }
+}
+
+/// Name of a color as accepted by `Color::try_from`, e.g. `dark_yellow`
+pub fn color_name(color: Color) -> Option<String> {
+    match color {
+        Color::Rgb { .. } | Color::AnsiValue(_) | Color::Reset => None,
+        color => {
+            let mut name = String::new();
+            for (i, c) in format!("{:?}", color).chars().enumerate() {
+                if c.is_uppercase() && i > 0 {
+                    name.push('_');
+                }
+                name.push(c.to_ascii_lowercase());
             }
+            Some(name)
+        }
+    }
+}
+
+/// Badge shown in front of a record that has duplicates folded into it, e.g. `(×1,532) `
+fn fold_badge(folded: usize) -> String {
+    if folded == 0 {
+        String::new()
+    } else {
+        format!("(×{}) ", format_count(folded + 1))
+    }
+}
+
+/// Format a count with thousands separators, e.g. `3,112`
+pub fn format_count(count: usize) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
         }
+        formatted.push(c);
+    }
+    formatted
+}
+
+/// Pad or cut `text` to exactly `width` characters
+fn fit(text: &str, width: usize) -> String {
+    let mut cell: String = text
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .take(width)
+        .collect();
+    let len = cell.chars().count();
+    cell.extend(std::iter::repeat_n(' ', width - len));
+    cell
+}
+
+/// Color of a stacked bar at panel row `level` (1 is the bottom): errors are
+/// stacked at the bottom, then warnings, then everything else
+fn stack_color(counts: [usize; LEVEL_GROUPS], max: usize, level: usize, rows: usize) -> Color {
+    let share = |count: usize| count as f64 / max as f64 * rows as f64;
+    let errors = share(counts[GROUP_ERROR]);
+    let warnings = errors + share(counts[GROUP_WARN]);
+
+    let position = level as f64 - 0.5;
+    if position < errors {
+        Color::Red
+    } else if position < warnings {
+        Color::Yellow
+    } else if counts[GROUP_OTHER] > 0 {
+        Color::Green
+    } else {
+        Color::Grey
+    }
+}
+
+fn level_color(level: Level) -> Color {
+    match level {
+        Level::Trace | Level::Debug => Color::DarkGrey,
+        Level::Info => Color::Green,
+        Level::Warn => Color::Yellow,
+        Level::Error | Level::Fatal => Color::Red,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_file::testing::open_test_log;
+
+    #[test]
+    fn test_table_navigation() {
+        let long = "x".repeat(150);
+        let content: String = (0..6)
+            .map(|i| {
+                let message = if i == 4 { long.as_str() } else { "short" };
+                format!("{{\"ts\":\"2024-01-02T10:00:0{}Z\",\"level\":\"info\",\"msg\":\"{}\"}}\n", i, message)
+            })
+            .collect();
+        let (_file, lf) = open_test_log(&content);
+        let format = lf.format().cloned();
+
+        let mut viewer = LogViewer::new();
+        viewer.set_wrap_limit(10);
+        let line_rows = |viewer: &LogViewer, i: usize| {
+            viewer.line_rows(lf.get_line(i).unwrap(), 0, format.as_ref(), 80)
+        };
+        // Raw lines wrap at the full width, table rows at the message column
+        assert_eq!(line_rows(&viewer, 4), 3);
+        assert_eq!(lf.get_end_of_file(6, |i| line_rows(&viewer, i)), (2, 5));
+        assert_eq!(lf.get_pos_from_end_line(5, 4, |i| line_rows(&viewer, i)), (3, 4));
+
+        viewer.toggle_table_view();
+        assert_eq!(line_rows(&viewer, 0), 1);
+        assert_eq!(line_rows(&viewer, 4), 5);
+
+        // G and page up fit the pages to the table rows as drawn
+        assert_eq!(lf.get_end_of_file(6, |i| line_rows(&viewer, i)), (4, 5));
+        assert_eq!(lf.get_pos_from_end_line(5, 4, |i| line_rows(&viewer, i)), (4, 4));
     }
 }
@@ -1,9 +1,29 @@
 mod command_handler;
+mod completion;
+mod config;
 mod controller;
+mod diff;
+mod extract;
+mod history;
+mod keymap;
+mod line_editor;
 mod log_file;
+mod log_format;
 mod log_viewer;
+mod matcher;
+mod message;
+mod normalize;
+mod notes;
+mod overlay;
+mod paths;
+mod query;
+mod session;
+mod stats;
+mod status;
+mod template;
+mod timeline;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use env_logger::{Builder, Target};
 use log::{info, LevelFilter};
 use std::panic;
@@ -16,17 +36,42 @@ use std::path::PathBuf; // We'll use PathBuf for safer file handling
 #[command(author, version, about, long_about = "This application allows you to view the content of a log file.")]
 struct Cli {
     filename: PathBuf, 
+
+    /// Log format definitions (TOML file or directory of TOML files)
+    #[arg(long = "formats")]
+    formats: Vec<PathBuf>,
+
+    /// Known-good log: lines whose normalized message also appears in it are hidden
+    #[arg(long = "baseline")]
+    baseline: Option<PathBuf>,
+
+    /// Config file to use instead of config.toml in the config dir
+    #[arg(long = "config")]
+    config: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
     let args= Cli::parse();
 
-    let target = Box::new(std::fs::File::create("app.log").unwrap());
+    let config = match args.config.clone().or_else(config::Config::default_path) {
+        Some(path) => config::Config::load(&path)?,
+        None => config::Config::default(),
+    };
 
-    Builder::new()
-        .target(Target::Pipe(target))
-        .filter_level(LevelFilter::Debug) // Set level here
-        .init();
+    if let Some(log_path) = config.debug_log_path() {
+        if let Some(dir) = log_path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let target = Box::new(
+            std::fs::File::create(&log_path)
+                .with_context(|| format!("Failed to create debug log {}", log_path.display()))?,
+        );
+
+        Builder::new()
+            .target(Target::Pipe(target))
+            .filter_level(LevelFilter::Debug) // Set level here
+            .init();
+    }
 
     panic::set_hook(Box::new(|panic_info| {
 
@@ -49,8 +94,31 @@ fn main() -> Result<()> {
     }));
 
     info!("Starting log viewer application");
+
+    // User formats from the config dir come first, then any given on the command line
+    let mut format_paths: Vec<PathBuf> = paths::config_dir()
+        .map(|dir| dir.join("formats"))
+        .filter(|dir| dir.exists())
+        .into_iter()
+        .collect();
+    format_paths.extend(args.formats);
+
+    let mut formats = Vec::new();
+    for path in &format_paths {
+        formats.extend(log_format::load_formats(path)?);
+    }
+
+    let baseline = match &args.baseline {
+        Some(baseline) => Some(
+            baseline
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("Invalid baseline path"))?,
+        ),
+        None => None,
+    };
+
     if let Some(path) = args.filename.to_str() {
-        let mut controller = controller::Controller::new(path)?;
+        let mut controller = controller::Controller::new(path, formats, baseline, config)?;
         controller.run()?;
     } else {
         eprintln!("Invalid file path provided.");
@@ -0,0 +1,112 @@
+use crate::log_format::{Level, LogFormat};
+
+/// Pattern used by search, hide/show and highlight.
+///
+/// Plain text matches anywhere in the line. `@name=value` matches lines whose
+/// parsed field `name` contains `value`; `@level=...` also accepts any spelling
+/// of the level (e.g. `@level=warn` matches `WARNING`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Matcher {
+    Text(String),
+    Field { name: String, value: String },
+}
+
+impl std::fmt::Display for Matcher {
+    /// The pattern as the user would type it
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Matcher::Text(pattern) => write!(f, "{}", pattern),
+            Matcher::Field { name, value } => write!(f, "@{}={}", name, value),
+        }
+    }
+}
+
+impl Matcher {
+    pub fn parse(pattern: &str) -> Self {
+        if let Some(rest) = pattern.strip_prefix('@') {
+            if let Some((name, value)) = rest.split_once('=') {
+                if !name.is_empty() {
+                    return Matcher::Field {
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    };
+                }
+            }
+        }
+        Matcher::Text(pattern.to_string())
+    }
+
+    pub fn matches(&self, line: &str, format: Option<&LogFormat>) -> bool {
+        match self {
+            Matcher::Text(pattern) => !pattern.is_empty() && line.contains(pattern.as_str()),
+            Matcher::Field { name, value } => field_match(name, value, line, format).is_some(),
+        }
+    }
+
+    /// Byte ranges of the line covered by this matcher
+    pub fn spans(&self, line: &str, format: Option<&LogFormat>) -> Vec<(usize, usize)> {
+        match self {
+            Matcher::Text(pattern) => find_all(line, pattern),
+            // Highlight the field value where the format parsed it
+            Matcher::Field { name, value } => field_match(name, value, line, format)
+                .flatten()
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+/// `None` unless field `name` of the parsed line matches `value`, otherwise
+/// where the field's value is, when the format knows
+fn field_match(
+    name: &str,
+    value: &str,
+    line: &str,
+    format: Option<&LogFormat>,
+) -> Option<Option<(usize, usize)>> {
+    let record = format.and_then(|f| f.parse(line))?;
+    let field = record.get(name)?;
+
+    let level_matches =
+        name == "level" && record.level.is_some() && record.level == Level::parse(value);
+    if !field.contains(value) && !level_matches {
+        return None;
+    }
+    Some(record.span(name))
+}
+
+fn find_all(line: &str, pattern: &str) -> Vec<(usize, usize)> {
+    let mut matches = Vec::new();
+    if pattern.is_empty() {
+        return matches;
+    }
+
+    let mut search_start = 0;
+    while let Some(start) = line[search_start..].find(pattern) {
+        let abs_start = search_start + start;
+        let abs_end = abs_start + pattern.len();
+        matches.push((abs_start, abs_end));
+        search_start = abs_end;
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_format;
+
+    #[test]
+    fn test_field_spans() {
+        let matcher = Matcher::parse("@level=info");
+        let line = r#"{"msg":"level info","level":"info"}"#;
+        let format = log_format::detect(&[], &[line]);
+        assert!(matcher.matches(line, format.as_ref()));
+        assert_eq!(matcher.spans(line, format.as_ref()), vec![(29, 33)]);
+
+        let line = r#"msg="level=info" level=info"#;
+        let format = log_format::detect(&[], &[line]);
+        assert_eq!(matcher.spans(line, format.as_ref()), vec![(23, 27)]);
+        assert!(!Matcher::parse("@level=warn").matches(line, format.as_ref()));
+    }
+}
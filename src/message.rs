@@ -0,0 +1,47 @@
+use crossterm::style::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warn,
+    Error,
+}
+
+/// Feedback shown on the command line row until the next key press
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub severity: Severity,
+    pub text: String,
+}
+
+impl Message {
+    pub fn info(text: impl Into<String>) -> Self {
+        Message {
+            severity: Severity::Info,
+            text: text.into(),
+        }
+    }
+
+    pub fn warn(text: impl Into<String>) -> Self {
+        Message {
+            severity: Severity::Warn,
+            text: text.into(),
+        }
+    }
+
+    pub fn error(text: impl Into<String>) -> Self {
+        Message {
+            severity: Severity::Error,
+            text: text.into(),
+        }
+    }
+
+    /// Text color, `None` for the terminal default
+    pub fn color(&self) -> Option<Color> {
+        match self.severity {
+            Severity::Info => None,
+            Severity::Warn => Some(Color::Yellow),
+            Severity::Error => Some(Color::Red),
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+static UUID: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}\b")
+        .expect("valid uuid regex")
+});
+
+static HEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(0x[0-9a-f]+|[0-9a-f]*[0-9][0-9a-f]*[a-f][0-9a-f]*|[0-9a-f]*[a-f][0-9a-f]*[0-9][0-9a-f]*)\b")
+        .expect("valid hex regex")
+});
+
+static NUMBER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\d+(\.\d+)?").expect("valid number regex"));
+
+/// Replace the variable parts of a line (UUIDs, hex ids, numbers) with
+/// placeholders so that lines differing only in those parts compare equal.
+pub fn mask(line: &str) -> String {
+    let masked = UUID.replace_all(line, "<uuid>");
+    let masked = HEX.replace_all(&masked, |caps: &regex::Captures| {
+        let token = &caps[0];
+        // Short mixed tokens are usually words like "ab1", not ids
+        if token.len() >= 8 || token.starts_with("0x") || token.starts_with("0X") {
+            "<hex>".to_string()
+        } else {
+            token.to_string()
+        }
+    });
+    NUMBER.replace_all(&masked, "<num>").into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(
+            mask("2024-01-02 10:00:01 retry 3 for 123e4567-e89b-12d3-a456-426614174000"),
+            "<num>-<num>-<num> <num>:<num>:<num> retry <num> for <uuid>"
+        );
+        assert_eq!(mask("ptr 0x7ffd1234 sha deadbeef42"), "ptr <hex> sha <hex>");
+        assert_eq!(mask("took 1.5ms"), "took <num>ms");
+        assert_eq!(mask("no ids here"), "no ids here");
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::log_file::LogFile;
+
+/// On-disk layout of the sidecar: notes by file fingerprint, then by byte
+/// offset of the annotated line
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct NotesFile {
+    files: BTreeMap<String, BTreeMap<usize, String>>,
+}
+
+/// Annotations on the lines of one log file, kept in `<log>.notes.json`
+#[derive(Debug)]
+pub struct Notes {
+    /// Where the notes are saved; `None` keeps them in memory only
+    sidecar: Option<PathBuf>,
+    fingerprint: String,
+    file: NotesFile,
+}
+
+impl Notes {
+    pub fn sidecar_path(log_path: &Path) -> PathBuf {
+        let mut name = log_path.as_os_str().to_owned();
+        name.push(".notes.json");
+        PathBuf::from(name)
+    }
+
+    /// Load the notes of `lf`; a missing sidecar means no notes
+    pub fn load(log_path: &Path, lf: &LogFile) -> Result<Notes> {
+        let sidecar = Notes::sidecar_path(log_path);
+        let file = match fs::read_to_string(&sidecar) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("Invalid notes file {}", sidecar.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => NotesFile::default(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", sidecar.display()))
+            }
+        };
+
+        Ok(Notes {
+            sidecar: Some(sidecar),
+            fingerprint: lf.fingerprint(),
+            file,
+        })
+    }
+
+    /// Notes of `lf` that are never saved, for when the sidecar cannot be
+    /// read and must not be overwritten
+    pub fn in_memory(lf: &LogFile) -> Notes {
+        Notes {
+            sidecar: None,
+            fingerprint: lf.fingerprint(),
+            file: NotesFile::default(),
+        }
+    }
+
+    fn notes(&self) -> Option<&BTreeMap<usize, String>> {
+        self.file.files.get(&self.fingerprint)
+    }
+
+    pub fn get(&self, lf: &LogFile, line: usize) -> Option<&str> {
+        let offset = lf.line_offset(line)?;
+        self.notes()?.get(&offset).map(String::as_str)
+    }
+
+    /// Annotate a line, or remove its note when `text` is empty, and save the sidecar
+    pub fn set(&mut self, lf: &LogFile, line: usize, text: &str) -> Result<()> {
+        let Some(offset) = lf.line_offset(line) else {
+            return Ok(());
+        };
+
+        let notes = self.file.files.entry(self.fingerprint.clone()).or_default();
+        if text.is_empty() {
+            notes.remove(&offset);
+        } else {
+            notes.insert(offset, text.to_string());
+        }
+        if notes.is_empty() {
+            self.file.files.remove(&self.fingerprint);
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(sidecar) = &self.sidecar else {
+            return Ok(());
+        };
+        let content = serde_json::to_string_pretty(&self.file)?;
+        fs::write(sidecar, content)
+            .with_context(|| format!("Failed to write {}", sidecar.display()))
+    }
+
+    /// Annotated lines and their notes, in file order
+    pub fn lines<'a>(&'a self, lf: &LogFile) -> Vec<(usize, &'a str)> {
+        self.notes()
+            .into_iter()
+            .flatten()
+            .filter_map(|(offset, text)| Some((lf.line_at_offset(*offset)?, text.as_str())))
+            .collect()
+    }
+
+    /// The notes as a markdown timeline, each followed by the annotated record
+    pub fn to_markdown(&self, lf: &LogFile, title: &str) -> String {
+        let mut md = format!("# Notes: {}\n", title);
+        for (line, text) in self.lines(lf) {
+            let timestamp = lf
+                .get_line(line)
+                .and_then(|l| lf.format()?.parse(l)?.timestamp);
+            match timestamp {
+                Some(ts) => md.push_str(&format!("\n## {} (line {})\n\n", ts, line + 1)),
+                None => md.push_str(&format!("\n## Line {}\n\n", line + 1)),
+            }
+            md.push_str(&format!("{}\n\n```\n{}\n```\n", text, lf.get_record(line).unwrap_or_default()));
+        }
+        md
+    }
+
+    pub fn export_markdown(&self, lf: &LogFile, title: &str, path: &str) -> Result<()> {
+        fs::write(path, self.to_markdown(lf, title))
+            .with_context(|| format!("Failed to write {}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_notes() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("app.log");
+        let mut file = fs::File::create(&log_path).unwrap();
+        write!(file, "10:00:00 start\n10:00:05 failover\n10:00:09 recovered\n").unwrap();
+        let lf = LogFile::new(&log_path).unwrap();
+
+        let mut notes = Notes::load(&log_path, &lf).unwrap();
+        assert!(notes.lines(&lf).is_empty());
+        notes.set(&lf, 1, "failover started here").unwrap();
+        notes.set(&lf, 2, "back").unwrap();
+        notes.set(&lf, 2, "").unwrap();
+
+        // Appending keeps the notes attached to the same lines
+        writeln!(file, "10:00:10 done").unwrap();
+        let lf = LogFile::new(&log_path).unwrap();
+        let notes = Notes::load(&log_path, &lf).unwrap();
+        assert_eq!(notes.get(&lf, 1), Some("failover started here"));
+        assert_eq!(notes.lines(&lf), vec![(1, "failover started here")]);
+
+        let md = notes.to_markdown(&lf, "app.log");
+        assert_eq!(
+            md,
+            "# Notes: app.log\n\n## Line 2\n\nfailover started here\n\n```\n10:00:05 failover\n```\n"
+        );
+
+        // An unreadable sidecar is left alone by in-memory notes
+        let sidecar = Notes::sidecar_path(&log_path);
+        fs::write(&sidecar, "{not json").unwrap();
+        assert!(Notes::load(&log_path, &lf).is_err());
+        let mut notes = Notes::in_memory(&lf);
+        notes.set(&lf, 0, "kept in memory").unwrap();
+        assert_eq!(notes.get(&lf, 0), Some("kept in memory"));
+        assert_eq!(fs::read_to_string(&sidecar).unwrap(), "{not json");
+    }
+}
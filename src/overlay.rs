@@ -0,0 +1,39 @@
+/// A scrollable list drawn over the log view (patterns, stats, marks...)
+pub struct ListOverlay {
+    pub title: String,
+    pub header: String,
+    pub rows: Vec<String>,
+    pub selected: usize,
+    pub scroll: usize,
+    /// Key help shown in the status bar
+    pub footer: String,
+}
+
+impl ListOverlay {
+    pub fn new(title: &str, header: &str, rows: Vec<String>) -> Self {
+        ListOverlay {
+            title: title.to_string(),
+            header: header.to_string(),
+            rows,
+            selected: 0,
+            scroll: 0,
+            footer: String::new(),
+        }
+    }
+
+    /// Move the selection by `delta` rows, scrolling so it stays within `height` rows
+    pub fn move_selection(&mut self, delta: isize, height: usize) {
+        if self.rows.is_empty() {
+            return;
+        }
+
+        let last = self.rows.len() - 1;
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if height > 0 && self.selected >= self.scroll + height {
+            self.scroll = self.selected + 1 - height;
+        }
+    }
+}
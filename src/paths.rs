@@ -0,0 +1,22 @@
+use std::env;
+use std::path::PathBuf;
+
+const APP_DIR: &str = "logviewer";
+
+/// Directory holding user configuration ($XDG_CONFIG_HOME/logviewer or ~/.config/logviewer)
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// Directory holding saved state such as sessions ($XDG_DATA_HOME/logviewer or ~/.local/share/logviewer)
+pub fn data_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    let base = match env::var_os(var) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(fallback),
+    };
+    Some(base.join(APP_DIR))
+}
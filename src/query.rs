@@ -0,0 +1,692 @@
+use anyhow::{anyhow, bail, Result};
+use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::extract;
+use crate::log_file::LogFile;
+use crate::log_format::Record;
+
+/// Widest a column of the result table gets
+const MAX_COLUMN_WIDTH: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Aggregate {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    /// The whole record
+    All,
+    Field(String),
+    /// An aggregate over a field, or over records for `count(*)`
+    Aggregate(Aggregate, Option<String>),
+}
+
+impl Item {
+    fn name(&self) -> String {
+        match self {
+            Item::All => "record".to_string(),
+            Item::Field(name) => name.clone(),
+            Item::Aggregate(agg, field) => format!(
+                "{}({})",
+                format!("{:?}", agg).to_lowercase(),
+                field.as_deref().unwrap_or("*")
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Like,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Compare(String, Op, String),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+/// A parsed `select ... from log [where ...] [group by ...] [order by ...] [limit N]`
+#[derive(Debug, Clone)]
+pub struct Query {
+    items: Vec<Item>,
+    filter: Option<Expr>,
+    group_by: Vec<String>,
+    /// Result column to sort on, descending when true
+    order_by: Option<(usize, bool)>,
+    limit: Option<usize>,
+}
+
+/// One row of a query result and the records it was computed from
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultRow {
+    pub values: Vec<String>,
+    /// Values of the key fields shared by the records behind the row (see `Query::row_key`)
+    pub key: Vec<Option<String>>,
+    /// First line of each record behind the row, in file order
+    pub lines: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<ResultRow>,
+}
+
+impl QueryResult {
+    /// Header and rows as aligned text lines
+    pub fn table(&self) -> (String, Vec<String>) {
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                self.rows
+                    .iter()
+                    .map(|row| row.values[i].chars().count())
+                    .chain([column.chars().count()])
+                    .max()
+                    .unwrap_or(0)
+                    .min(MAX_COLUMN_WIDTH)
+            })
+            .collect();
+
+        let line = |values: &[String]| {
+            values
+                .iter()
+                .zip(&widths)
+                .map(|(value, width)| {
+                    let value: String = value.chars().take(*width).collect();
+                    format!("{:<width$}", value, width = width)
+                })
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        };
+
+        (
+            line(&self.columns),
+            self.rows.iter().map(|row| line(&row.values)).collect(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Symbol(&'static str),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    const SYMBOLS: [&str; 11] = ["<=", ">=", "!=", "<>", "==", "=", "<", ">", ",", "(", ")"];
+
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+    while !rest.is_empty() {
+        if let Some(quote) = rest.chars().next().filter(|c| *c == '\'' || *c == '"') {
+            let end = rest[1..]
+                .find(quote)
+                .ok_or_else(|| anyhow!("Unterminated string: {}", rest))?;
+            tokens.push(Token::Str(rest[1..end + 1].to_string()));
+            rest = &rest[end + 2..];
+        } else if rest.starts_with('*') {
+            tokens.push(Token::Symbol("*"));
+            rest = &rest[1..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || "'\",()=<>!*".contains(c))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                bail!("Unexpected character in query: {}", rest);
+            }
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.at_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            bail!("Expected '{}' in query", keyword)
+        }
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn word(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Word(w)) => Ok(w),
+            other => bail!("Expected a name, found {:?}", other),
+        }
+    }
+
+    fn literal(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Word(w)) | Some(Token::Str(w)) => Ok(w),
+            other => bail!("Expected a value, found {:?}", other),
+        }
+    }
+
+    fn item(&mut self) -> Result<Item> {
+        if self.eat_symbol("*") {
+            return Ok(Item::All);
+        }
+        let name = self.word()?;
+        let aggregate = match name.to_lowercase().as_str() {
+            "count" => Aggregate::Count,
+            "sum" => Aggregate::Sum,
+            "avg" => Aggregate::Avg,
+            "min" => Aggregate::Min,
+            "max" => Aggregate::Max,
+            _ => return Ok(Item::Field(name)),
+        };
+        if !self.eat_symbol("(") {
+            return Ok(Item::Field(name));
+        }
+        let field = if self.eat_symbol("*") {
+            if aggregate != Aggregate::Count {
+                bail!("Only count accepts *");
+            }
+            None
+        } else {
+            Some(self.word()?)
+        };
+        if !self.eat_symbol(")") {
+            bail!("Expected ')' after {}", name);
+        }
+        Ok(Item::Aggregate(aggregate, field))
+    }
+
+    fn or_expr(&mut self) -> Result<Expr> {
+        let mut expr = self.and_expr()?;
+        while self.eat_keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr> {
+        let mut expr = self.unary_expr()?;
+        while self.eat_keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary_expr(&mut self) -> Result<Expr> {
+        if self.eat_keyword("not") {
+            return Ok(Expr::Not(Box::new(self.unary_expr()?)));
+        }
+        if self.eat_symbol("(") {
+            let expr = self.or_expr()?;
+            if !self.eat_symbol(")") {
+                bail!("Expected ')' in where clause");
+            }
+            return Ok(expr);
+        }
+
+        let field = self.word()?;
+        let op = match self.next() {
+            Some(Token::Symbol("=")) | Some(Token::Symbol("==")) => Op::Eq,
+            Some(Token::Symbol("!=")) | Some(Token::Symbol("<>")) => Op::Ne,
+            Some(Token::Symbol("<")) => Op::Lt,
+            Some(Token::Symbol("<=")) => Op::Le,
+            Some(Token::Symbol(">")) => Op::Gt,
+            Some(Token::Symbol(">=")) => Op::Ge,
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case("like") => Op::Like,
+            other => bail!("Expected a comparison after {}, found {:?}", field, other),
+        };
+        Ok(Expr::Compare(field, op, self.literal()?))
+    }
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Query> {
+        let mut p = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+        };
+
+        p.expect_keyword("select")?;
+        let mut items = vec![p.item()?];
+        while p.eat_symbol(",") {
+            items.push(p.item()?);
+        }
+
+        if p.eat_keyword("from") {
+            let table = p.word()?;
+            if table != "log" {
+                bail!("Unknown table '{}', only 'log' can be queried", table);
+            }
+        }
+
+        let filter = if p.eat_keyword("where") {
+            Some(p.or_expr()?)
+        } else {
+            None
+        };
+
+        let mut group_by = Vec::new();
+        if p.eat_keyword("group") {
+            p.expect_keyword("by")?;
+            group_by.push(p.word()?);
+            while p.eat_symbol(",") {
+                group_by.push(p.word()?);
+            }
+        }
+
+        let mut order_by = None;
+        if p.eat_keyword("order") {
+            p.expect_keyword("by")?;
+            let item = p.item()?;
+            let column = match &item {
+                Item::Field(name) if name.parse::<usize>().is_ok() => {
+                    name.parse::<usize>().unwrap().checked_sub(1)
+                }
+                _ => items.iter().position(|i| *i == item),
+            }
+            .filter(|c| *c < items.len())
+            .ok_or_else(|| anyhow!("Cannot order by {}, it is not selected", item.name()))?;
+            let descending = p.eat_keyword("desc");
+            if !descending {
+                p.eat_keyword("asc");
+            }
+            order_by = Some((column, descending));
+        }
+
+        let limit = if p.eat_keyword("limit") {
+            let n = p.word()?;
+            Some(n.parse().map_err(|_| anyhow!("Invalid limit: {}", n))?)
+        } else {
+            None
+        };
+
+        if let Some(token) = p.peek() {
+            bail!("Unexpected {:?} in query", token);
+        }
+
+        Ok(Query {
+            items,
+            filter,
+            group_by,
+            order_by,
+            limit,
+        })
+    }
+
+    fn is_aggregate(&self) -> bool {
+        !self.group_by.is_empty() || self.items.iter().any(|i| matches!(i, Item::Aggregate(..)))
+    }
+
+    /// Fields telling the result rows apart: the group by fields, or the
+    /// line number when each record is a row of its own
+    fn key(&self, row: &Row) -> Vec<Option<String>> {
+        if self.is_aggregate() {
+            self.group_by.iter().map(|g| row.get(g)).collect()
+        } else {
+            // Not `row.get("line")`, records may have a `line` field of their own
+            vec![Some((row.line_number + 1).to_string())]
+        }
+    }
+
+    /// Key of the result row the record starting at `start` belongs to, `None`
+    /// when the where clause leaves it out
+    pub fn row_key(&self, lf: &LogFile, start: usize) -> Option<Vec<Option<String>>> {
+        let line = lf.get_line(start)?;
+        let record = lf.format().and_then(|f| f.parse(line));
+        let row = Row {
+            line_number: start,
+            text: line,
+            record: record.as_ref(),
+        };
+        self.filter
+            .as_ref()
+            .is_none_or(|f| row.matches(f))
+            .then(|| self.key(&row))
+    }
+
+    /// Run the query over the visible records of a file
+    pub fn run(&self, lf: &LogFile) -> QueryResult {
+        // Records, or groups of records, in order of first appearance
+        let mut groups: Vec<(Vec<Option<String>>, Vec<usize>)> = Vec::new();
+        let mut group_index: HashMap<Vec<Option<String>>, usize> = HashMap::new();
+        let mut rows = Vec::new();
+        // Aggregates over the whole file give one row, even without records
+        if self.is_aggregate() && self.group_by.is_empty() {
+            groups.push((Vec::new(), Vec::new()));
+            group_index.insert(Vec::new(), 0);
+        }
+
+        let mut start = 0;
+        while start < lf.total_lines() {
+            if lf.is_line_visible(start) {
+                if let Some(line) = lf.get_line(start) {
+                    let record = lf.format().and_then(|f| f.parse(line));
+                    let row = Row {
+                        line_number: start,
+                        text: line,
+                        record: record.as_ref(),
+                    };
+                    if self.filter.as_ref().is_none_or(|f| row.matches(f)) {
+                        let key = self.key(&row);
+                        if self.is_aggregate() {
+                            let index = *group_index.entry(key.clone()).or_insert_with(|| {
+                                groups.push((key, Vec::new()));
+                                groups.len() - 1
+                            });
+                            groups[index].1.push(start);
+                        } else {
+                            rows.push(ResultRow {
+                                values: self.items.iter().map(|i| row.value(i)).collect(),
+                                key,
+                                lines: vec![start],
+                            });
+                        }
+                    }
+                }
+            }
+            start = lf.record_end(start);
+        }
+
+        if self.is_aggregate() {
+            rows = groups
+                .into_iter()
+                .map(|(key, lines)| ResultRow {
+                    values: self.items.iter().map(|i| aggregate(lf, i, &lines)).collect(),
+                    key,
+                    lines,
+                })
+                .collect();
+        }
+
+        if let Some((column, descending)) = self.order_by {
+            rows.sort_by(|a, b| {
+                let ordering = compare(&a.values[column], &b.values[column]);
+                if descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+        }
+        if let Some(limit) = self.limit {
+            rows.truncate(limit);
+        }
+
+        QueryResult {
+            columns: self.items.iter().map(Item::name).collect(),
+            rows,
+        }
+    }
+}
+
+/// A record being queried
+struct Row<'a> {
+    line_number: usize,
+    text: &'a str,
+    record: Option<&'a Record>,
+}
+
+impl Row<'_> {
+    /// A field of the record; `line` is the line number and, for unparsed
+    /// lines, `message` the whole line
+    fn get(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.record.and_then(|r| r.get(name)) {
+            return Some(value.to_string());
+        }
+        match name {
+            "line" => Some((self.line_number + 1).to_string()),
+            "message" if self.record.is_none() => Some(self.text.to_string()),
+            _ => None,
+        }
+    }
+
+    fn value(&self, item: &Item) -> String {
+        match item {
+            Item::All => self.text.to_string(),
+            Item::Field(name) => self.get(name).unwrap_or_default(),
+            Item::Aggregate(..) => String::new(),
+        }
+    }
+
+    fn matches(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::And(a, b) => self.matches(a) && self.matches(b),
+            Expr::Or(a, b) => self.matches(a) || self.matches(b),
+            Expr::Not(e) => !self.matches(e),
+            Expr::Compare(field, op, literal) => {
+                let Some(value) = self.get(field) else {
+                    return false;
+                };
+                let ordering = compare(&value, literal);
+                match op {
+                    Op::Eq => ordering == Ordering::Equal,
+                    Op::Ne => ordering != Ordering::Equal,
+                    Op::Lt => ordering == Ordering::Less,
+                    Op::Le => ordering != Ordering::Greater,
+                    Op::Gt => ordering == Ordering::Greater,
+                    Op::Ge => ordering != Ordering::Less,
+                    Op::Like => like(&value, literal),
+                }
+            }
+        }
+    }
+}
+
+/// Numbers compare numerically, anything else as case-insensitive text
+fn compare(a: &str, b: &str) -> Ordering {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(x), Ok(y)) => x.total_cmp(&y),
+        _ => a.to_lowercase().cmp(&b.to_lowercase()),
+    }
+}
+
+/// SQL `like`: `%` matches any text, `_` any character, case-insensitively
+fn like(value: &str, pattern: &str) -> bool {
+    let mut regex = String::from("(?is)^");
+    for c in pattern.chars() {
+        match c {
+            '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).is_ok_and(|r| r.is_match(value))
+}
+
+fn aggregate(lf: &LogFile, item: &Item, lines: &[usize]) -> String {
+    let rows = lines.iter().filter_map(|&start| {
+        let text = lf.get_line(start)?;
+        Some((start, text, lf.format().and_then(|f| f.parse(text))))
+    });
+
+    let (agg, field) = match item {
+        Item::Aggregate(agg, field) => (*agg, field),
+        // Plain items show the value of the group's first record
+        _ => {
+            return rows
+                .map(|(line_number, text, record)| {
+                    Row {
+                        line_number,
+                        text,
+                        record: record.as_ref(),
+                    }
+                    .value(item)
+                })
+                .next()
+                .unwrap_or_default()
+        }
+    };
+
+    let Some(field) = field else {
+        return lines.len().to_string();
+    };
+    let values: Vec<String> = rows
+        .filter_map(|(line_number, text, record)| {
+            Row {
+                line_number,
+                text,
+                record: record.as_ref(),
+            }
+            .get(field)
+        })
+        .collect();
+    if agg == Aggregate::Count {
+        return values.len().to_string();
+    }
+
+    let numbers: Vec<f64> = values.iter().filter_map(|v| v.parse().ok()).collect();
+    if numbers.is_empty() {
+        return String::new();
+    }
+    let result = match agg {
+        Aggregate::Sum => numbers.iter().sum(),
+        Aggregate::Avg => numbers.iter().sum::<f64>() / numbers.len() as f64,
+        Aggregate::Min => numbers.iter().copied().fold(f64::INFINITY, f64::min),
+        Aggregate::Max => numbers.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        Aggregate::Count => unreachable!(),
+    };
+    extract::format_value(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_file::testing::open_test_log;
+    use crate::log_file::FilterRule;
+
+    #[test]
+    fn test_query() {
+        let content: String = [
+            ("info", 200, 12),
+            ("error", 500, 300),
+            ("warn", 503, 80),
+            ("error", 502, 100),
+            ("info", 404, 5),
+        ]
+        .iter()
+        .map(|(level, status, ms)| {
+            format!(
+                "{{\"ts\":\"2024-01-02T10:00:00Z\",\"level\":\"{}\",\"status\":\"{}\",\"ms\":\"{}\",\"msg\":\"GET /api\"}}\n",
+                level, status, ms
+            )
+        })
+        .collect();
+        let (_file, mut lf) = open_test_log(&content);
+
+        let query = Query::parse(
+            "select level, count(*), max(ms) from log where status >= 500 group by level order by count(*) desc",
+        )
+        .unwrap();
+        let result = query.run(&lf);
+        assert_eq!(result.columns, vec!["level", "count(*)", "max(ms)"]);
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(result.rows[0].values, vec!["error", "2", "300"]);
+        assert_eq!(result.rows[0].lines, vec![1, 3]);
+        assert_eq!(result.rows[1].values, vec!["warn", "1", "80"]);
+        assert_eq!(result.rows[0].key, vec![Some("error".to_string())]);
+        assert_eq!(query.row_key(&lf, 3), Some(vec![Some("error".to_string())]));
+        assert_eq!(query.row_key(&lf, 0), None);
+
+        let result = Query::parse("select line, ms where level = 'INFO' or ms > 90 limit 3")
+            .unwrap()
+            .run(&lf);
+        let lines: Vec<&str> = result.rows.iter().map(|r| r.values[0].as_str()).collect();
+        assert_eq!(lines, vec!["1", "2", "4"]);
+
+        let result = Query::parse("select count(*) where msg like 'get%' and not status = 200")
+            .unwrap()
+            .run(&lf);
+        assert_eq!(result.rows[0].values, vec!["4"]);
+
+        let result = Query::parse("select count(*), max(ms) from log where status >= 600")
+            .unwrap()
+            .run(&lf);
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0].values, vec!["0", ""]);
+
+        assert!(Query::parse("select level from other").is_err());
+        assert!(Query::parse("select level order by ms").is_err());
+        assert!(Query::parse("select level where").is_err());
+
+        lf.apply_filter(FilterRule::HideQuery {
+            query: "select level, count(*) where status >= 500 group by level".to_string(),
+            key: vec![Some("error".to_string())],
+        })
+        .unwrap();
+        assert_eq!(lf.visible_lines(), 3);
+        assert!(!lf.is_line_visible(1));
+
+        // Plain rows are keyed by line number even when records have a `line` field
+        let source_line = "{\"ts\":\"2024-01-02T10:00:00Z\",\"line\":42,\"msg\":\"retry\"}\n";
+        let (_file, mut lf) = open_test_log(&source_line.repeat(3));
+        let query = "select msg";
+        assert_eq!(Query::parse(query).unwrap().row_key(&lf, 1), Some(vec![Some("2".to_string())]));
+        lf.apply_filter(FilterRule::HideQuery {
+            query: query.to_string(),
+            key: vec![Some("2".to_string())],
+        })
+        .unwrap();
+        assert_eq!(lf.visible_lines(), 2);
+        assert!(!lf.is_line_visible(1));
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::log_file::{self, DedupMode, FilterRule};
+use crate::paths;
+
+/// What is restored when a file is reopened. Positions and marks are line
+/// numbers, which stay valid when a log is appended to.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub format: Option<String>,
+    pub dedup: Option<DedupMode>,
+    pub filters: Vec<FilterRule>,
+    /// Pattern and color name of each highlight
+    pub highlights: Vec<(String, String)>,
+    pub search: Option<String>,
+    pub marks: BTreeMap<char, usize>,
+    pub start_line: usize,
+    pub cursor: (u16, u16),
+}
+
+impl Session {
+    /// `None` when there is no session with this name
+    pub fn load(path: &Path) -> Result<Option<Session>> {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map(Some)
+                .with_context(|| format!("Invalid session file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+fn sessions_dir() -> Result<PathBuf> {
+    paths::data_dir()
+        .map(|dir| dir.join("sessions"))
+        .ok_or_else(|| anyhow!("No data directory, set HOME or XDG_DATA_HOME"))
+}
+
+/// Session saved on quit for the file at `log_path`
+pub fn auto_path(log_path: &Path) -> Result<PathBuf> {
+    let path = fs::canonicalize(log_path).unwrap_or_else(|_| log_path.to_path_buf());
+    let hash = log_file::fnv1a(path.as_os_str().as_encoded_bytes());
+    let name = path.file_name().map_or("log".into(), |n| n.to_string_lossy());
+    Ok(sessions_dir()?.join("auto").join(format!("{}-{:016x}.json", name, hash)))
+}
+
+/// Session saved under a name with `session save NAME`
+pub fn named_path(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(anyhow!("Invalid session name: {}", name));
+    }
+    Ok(sessions_dir()?.join(format!("{}.json", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sessions").join("triage.json");
+        assert_eq!(Session::load(&path).unwrap(), None);
+
+        let session = Session {
+            format: Some("json".to_string()),
+            filters: vec![
+                FilterRule::Hide("healthcheck".to_string()),
+                FilterRule::HideRecord(12),
+                FilterRule::ShowTemplate("Connected to <*>".to_string()),
+            ],
+            highlights: vec![("@level=error".to_string(), "red".to_string())],
+            marks: BTreeMap::from([('a', 12)]),
+            start_line: 40,
+            ..Session::default()
+        };
+        session.save(&path).unwrap();
+        assert_eq!(Session::load(&path).unwrap(), Some(session));
+
+        assert!(named_path("../x").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::{Context, Result};
+use chrono::{NaiveDateTime, Timelike};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+use crate::log_file::LogFile;
+use crate::log_format::Level;
+use crate::log_viewer::format_count;
+use crate::template;
+
+/// Entries listed in the "top" sections
+const TOP_N: usize = 10;
+
+/// Summary of the visible records of a file, as report lines
+pub fn summarize(lf: &LogFile, field: Option<&str>) -> Vec<String> {
+    let format = lf.format();
+
+    let mut records = 0;
+    let mut levels: BTreeMap<Level, usize> = BTreeMap::new();
+    let mut no_level = 0;
+    let mut first: Option<NaiveDateTime> = None;
+    let mut last: Option<NaiveDateTime> = None;
+    let mut minutes: HashMap<NaiveDateTime, usize> = HashMap::new();
+    let mut values: HashMap<String, usize> = HashMap::new();
+
+    let mut start = 0;
+    while start < lf.total_lines() {
+        if lf.is_line_visible(start) {
+            records += 1;
+            let record = lf
+                .get_line(start)
+                .and_then(|line| format.and_then(|f| f.parse(line)));
+
+            match record.as_ref().and_then(|r| r.level) {
+                Some(level) => *levels.entry(level).or_default() += 1,
+                None => no_level += 1,
+            }
+
+            if let Some(ts) = record.as_ref().and_then(|r| r.timestamp) {
+                first = Some(first.map_or(ts, |f| f.min(ts)));
+                last = Some(last.map_or(ts, |l| l.max(ts)));
+                let minute = ts.with_second(0).and_then(|t| t.with_nanosecond(0)).unwrap_or(ts);
+                *minutes.entry(minute).or_default() += 1;
+            }
+
+            if let (Some(field), Some(record)) = (field, &record) {
+                if let Some(value) = record.get(field) {
+                    *values.entry(value.to_string()).or_default() += 1;
+                }
+            }
+        }
+        start = lf.record_end(start);
+    }
+
+    let mut report = vec![
+        format!("Total lines:    {}", format_count(lf.total_lines())),
+        format!("Visible lines:  {}", format_count(lf.visible_lines())),
+        format!("Visible records: {}", format_count(records)),
+        String::new(),
+        "Levels".to_string(),
+    ];
+    for (level, count) in levels.iter().rev() {
+        report.push(format!("  {:<8} {:>10}", level.name(), format_count(*count)));
+    }
+    report.push(format!("  {:<8} {:>10}", "(none)", format_count(no_level)));
+    report.push(String::new());
+
+    match (first, last) {
+        (Some(first), Some(last)) => {
+            let span = last - first;
+            report.push(format!(
+                "Time span:      {} .. {} ({}h {:02}m {:02}s)",
+                first,
+                last,
+                span.num_hours(),
+                span.num_minutes() % 60,
+                span.num_seconds() % 60
+            ));
+            if let Some((minute, count)) = minutes
+                .iter()
+                .max_by_key(|(minute, count)| (**count, std::cmp::Reverse(**minute)))
+            {
+                report.push(format!(
+                    "Busiest minute: {} ({} lines)",
+                    minute.format("%Y-%m-%d %H:%M"),
+                    format_count(*count)
+                ));
+            }
+        }
+        _ => report.push("Time span:      no timestamps".to_string()),
+    }
+    report.push(String::new());
+
+    let mut templates = template::mine(lf);
+    templates.sort_by_key(|t| std::cmp::Reverse(t.count()));
+    report.push(format!("Top {} templates", TOP_N));
+    for t in templates.iter().take(TOP_N) {
+        report.push(format!("  {:>10}  {}", format_count(t.count()), t.text()));
+    }
+
+    if let Some(field) = field {
+        let mut values: Vec<(String, usize)> = values.into_iter().collect();
+        values.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        report.push(String::new());
+        report.push(format!("Top {} values of '{}'", TOP_N, field));
+        for (value, count) in values.iter().take(TOP_N) {
+            report.push(format!("  {:>10}  {}", format_count(*count), value));
+        }
+    }
+
+    report
+}
+
+/// Write a report as plain text
+pub fn export(report: &[String], path: &str) -> Result<()> {
+    fs::write(path, report.join("\n") + "\n")
+        .with_context(|| format!("Failed to write {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_file::testing::open_test_log;
+
+    #[test]
+    fn test_summarize() {
+        let content: String = [("00", "info", "bob"), ("30", "error", "amy"), ("59", "info", "bob")]
+            .iter()
+            .map(|(ts, level, user)| {
+                format!(
+                    "{{\"ts\":\"2024-01-02T10:00:{}Z\",\"level\":\"{}\",\"user\":\"{}\",\"msg\":\"login\"}}\n",
+                    ts, level, user
+                )
+            })
+            .collect();
+        let (_file, lf) = open_test_log(&content);
+
+        let report = summarize(&lf, Some("user"));
+        assert!(report.contains(&"Visible records: 3".to_string()));
+        assert!(report.contains(&format!("  {:<8} {:>10}", "ERROR", "1")));
+        assert!(report.contains(&"Busiest minute: 2024-01-02 10:00 (3 lines)".to_string()));
+        assert!(report.iter().any(|line| line.starts_with("Time span:") && line.ends_with("(0h 00m 59s)")));
+        assert_eq!(report.last().unwrap(), &format!("  {:>10}  amy", "1"));
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::{anyhow, Result};
+
+use crate::log_viewer::format_count;
+
+/// Status bar layout used unless the config sets `status_format`
+pub const DEFAULT_FORMAT: &str =
+    "{file} [{format}]  {line}/{total} {percent}%  {visible} shown  {filters} filters  {search}  {mode}  {note}";
+
+/// What the status bar can show, each as a `{name}` placeholder
+#[derive(Debug, Default)]
+pub struct StatusInfo {
+    pub file: String,
+    pub format: String,
+    /// Line under the cursor, counting from 1
+    pub line: usize,
+    pub total: usize,
+    pub visible: usize,
+    pub filters: usize,
+    /// Search pattern and where the cursor is among its matches; empty without a search
+    pub search: String,
+    pub mode: String,
+    /// Note on the line under the cursor; empty when there is none
+    pub note: String,
+}
+
+impl StatusInfo {
+    fn value(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "file" => self.file.clone(),
+            "format" => self.format.clone(),
+            "line" => format_count(self.line),
+            "total" => format_count(self.total),
+            "visible" => format_count(self.visible),
+            "percent" => (self.line * 100).checked_div(self.total).unwrap_or(100).to_string(),
+            "filters" => self.filters.to_string(),
+            "search" => self.search.clone(),
+            "mode" => self.mode.clone(),
+            "note" => self.note.clone(),
+            _ => return None,
+        })
+    }
+}
+
+/// Split `format` into literal text and placeholder names
+fn parts(format: &str) -> Vec<(&str, Option<&str>)> {
+    let mut parts = Vec::new();
+    let mut rest = format;
+    while let Some((open, close)) = rest
+        .find('{')
+        .and_then(|open| Some((open, open + rest[open..].find('}')?)))
+    {
+        parts.push((&rest[..open], Some(&rest[open + 1..close])));
+        rest = &rest[close + 1..];
+    }
+    parts.push((rest, None));
+    parts
+}
+
+/// Check that every placeholder in `format` is known
+pub fn validate(format: &str) -> Result<()> {
+    let info = StatusInfo::default();
+    for name in parts(format).into_iter().filter_map(|(_, name)| name) {
+        if info.value(name).is_none() {
+            return Err(anyhow!("Unknown status placeholder '{{{}}}'", name));
+        }
+    }
+    Ok(())
+}
+
+/// Expand the placeholders in `format`. One with nothing to show takes the
+/// spaces before it along, so optional parts leave no gaps.
+pub fn render(format: &str, info: &StatusInfo) -> String {
+    let mut status = String::new();
+    for (text, name) in parts(format) {
+        status.push_str(text);
+        match name.and_then(|name| info.value(name)) {
+            Some(value) if value.is_empty() => status.truncate(status.trim_end().len()),
+            Some(value) => status.push_str(&value),
+            None => {}
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let info = StatusInfo {
+            file: "app.log".to_string(),
+            format: "json".to_string(),
+            line: 250,
+            total: 1000,
+            visible: 1000,
+            search: "/timeout  match 3 of 57".to_string(),
+            mode: "Normal".to_string(),
+            ..StatusInfo::default()
+        };
+        assert_eq!(
+            render(DEFAULT_FORMAT, &info),
+            "app.log [json]  250/1,000 25%  1,000 shown  0 filters  /timeout  match 3 of 57  Normal"
+        );
+        assert_eq!(render("{line} {note}|{percent}%", &info), "250|25%");
+
+        assert!(validate(DEFAULT_FORMAT).is_ok());
+        let err = validate("{file} {lines}").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown status placeholder '{lines}'");
+    }
+}
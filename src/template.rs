@@ -0,0 +1,271 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::log_file::LogFile;
+use crate::normalize;
+
+/// Placeholder for a token that varies between lines of the same template
+pub const WILDCARD: &str = "<*>";
+
+/// Share of tokens two lines must have in common to share a template
+const SIMILARITY_THRESHOLD: f64 = 0.5;
+
+/// A message template and the records it was mined from
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub tokens: Vec<String>,
+    /// First line of each record matching the template, in file order
+    pub lines: Vec<usize>,
+}
+
+impl Template {
+    pub fn text(&self) -> String {
+        self.tokens.join(" ")
+    }
+
+    pub fn count(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn first(&self) -> usize {
+        self.lines.first().copied().unwrap_or(0)
+    }
+
+    pub fn last(&self) -> usize {
+        self.lines.last().copied().unwrap_or(0)
+    }
+
+    /// Fraction of positions where the tokens agree; wildcards agree with anything
+    fn similarity(&self, tokens: &[String]) -> f64 {
+        if tokens.is_empty() {
+            return 1.0;
+        }
+        let same = self
+            .tokens
+            .iter()
+            .zip(tokens)
+            .filter(|(a, b)| *a == WILDCARD || a == b)
+            .count();
+        same as f64 / tokens.len() as f64
+    }
+
+    fn merge(&mut self, tokens: &[String]) {
+        for (existing, token) in self.tokens.iter_mut().zip(tokens) {
+            if existing != token {
+                *existing = WILDCARD.to_string();
+            }
+        }
+    }
+}
+
+/// Drain-style clustering of log messages into templates.
+///
+/// Lines are masked (numbers, ids...) and tokenized, then bucketed by token
+/// count and first token. Within a bucket a line joins the most similar
+/// template, turning differing tokens into wildcards, or starts a new one.
+#[derive(Default)]
+pub struct TemplateMiner {
+    templates: Vec<Template>,
+    buckets: HashMap<(usize, String), Vec<usize>>,
+}
+
+impl TemplateMiner {
+    /// Add a message and return the index of its template
+    pub fn add(&mut self, message: &str, line_idx: usize) -> usize {
+        let tokens: Vec<String> = normalize::mask(message)
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+
+        let first = match tokens.first() {
+            Some(token) if !token.contains('<') => token.clone(),
+            _ => WILDCARD.to_string(),
+        };
+        let bucket = self.buckets.entry((tokens.len(), first)).or_default();
+
+        let best = bucket
+            .iter()
+            .map(|&id| (id, self.templates[id].similarity(&tokens)))
+            .filter(|(_, similarity)| *similarity >= SIMILARITY_THRESHOLD)
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        match best {
+            Some((id, _)) => {
+                let template = &mut self.templates[id];
+                template.merge(&tokens);
+                template.lines.push(line_idx);
+                id
+            }
+            None => {
+                let id = self.templates.len();
+                self.templates.push(Template {
+                    tokens,
+                    lines: vec![line_idx],
+                });
+                bucket.push(id);
+                id
+            }
+        }
+    }
+
+    pub fn into_templates(self) -> Vec<Template> {
+        self.templates
+    }
+}
+
+/// The text templates are mined from: the parsed message when the format has
+/// one, otherwise the whole line
+pub fn message_of<'a>(lf: &LogFile, line: &'a str) -> std::borrow::Cow<'a, str> {
+    match lf.format().and_then(|format| format.parse(line)) {
+        Some(record) => match record.get("message") {
+            Some(message) => std::borrow::Cow::Owned(message.to_string()),
+            None => std::borrow::Cow::Borrowed(line),
+        },
+        None => std::borrow::Cow::Borrowed(line),
+    }
+}
+
+/// A line's message with timestamps, ids and numbers masked, for comparing logs
+pub fn normalized_message(lf: &LogFile, line: &str) -> String {
+    normalize::mask(&message_of(lf, line))
+}
+
+/// Whether a message fits a template's text, each wildcard standing for one token
+pub fn matches(template: &str, message: &str) -> bool {
+    let masked = normalize::mask(message);
+    let tokens: Vec<&str> = masked.split_whitespace().collect();
+    let pattern: Vec<&str> = template.split_whitespace().collect();
+    tokens.len() == pattern.len()
+        && pattern.iter().zip(&tokens).all(|(p, t)| *p == WILDCARD || p == t)
+}
+
+/// First lines of the records of `lf` whose normalized message also occurs in `baseline`
+pub fn records_in_baseline(lf: &LogFile, baseline: &LogFile) -> Vec<usize> {
+    let mut known = HashSet::new();
+    let mut start = 0;
+    while start < baseline.total_lines() {
+        if let Some(line) = baseline.get_line(start) {
+            known.insert(normalized_message(baseline, line));
+        }
+        start = baseline.record_end(start);
+    }
+
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start < lf.total_lines() {
+        if let Some(line) = lf.get_line(start) {
+            if known.contains(&normalized_message(lf, line)) {
+                matches.push(start);
+            }
+        }
+        start = lf.record_end(start);
+    }
+    matches
+}
+
+/// Cluster the visible records of a file into templates
+pub fn mine(lf: &LogFile) -> Vec<Template> {
+    let mut miner = TemplateMiner::default();
+    let mut start = 0;
+    while start < lf.total_lines() {
+        if lf.is_line_visible(start) {
+            if let Some(line) = lf.get_line(start) {
+                miner.add(&message_of(lf, line), start);
+            }
+        }
+        start = lf.record_end(start);
+    }
+    miner.into_templates()
+}
+
+/// How rare a template must be for its records to count as anomalies
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rarity {
+    /// Templates seen at most this many times
+    Count(usize),
+    /// The rarest templates, together covering at most this percentage of records
+    Percentile(f64),
+}
+
+impl Rarity {
+    /// Parse `5` (count) or `1%` / `0.5%` (percentile)
+    pub fn parse(s: &str) -> Option<Rarity> {
+        match s.strip_suffix('%') {
+            Some(pct) => pct
+                .parse::<f64>()
+                .ok()
+                .filter(|p| (0.0..=100.0).contains(p))
+                .map(Rarity::Percentile),
+            None => s.parse::<usize>().ok().map(Rarity::Count),
+        }
+    }
+}
+
+/// First lines of the records whose template is rare
+pub fn rare_records(templates: &[Template], rarity: Rarity) -> BTreeSet<usize> {
+    let mut by_count: Vec<&Template> = templates.iter().collect();
+    by_count.sort_by_key(|t| t.count());
+
+    let total: usize = templates.iter().map(Template::count).sum();
+    let mut covered = 0;
+    let mut rare = BTreeSet::new();
+
+    for template in by_count {
+        let is_rare = match rarity {
+            Rarity::Count(max) => template.count() <= max,
+            Rarity::Percentile(pct) => {
+                covered += template.count();
+                covered as f64 <= total as f64 * pct / 100.0
+            }
+        };
+        if !is_rare {
+            break;
+        }
+        rare.extend(template.lines.iter().copied());
+    }
+    rare
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_file::testing::open_test_log;
+
+    #[test]
+    fn test_mining() {
+        let mut miner = TemplateMiner::default();
+        let a = miner.add("Connected to db-1 in 12ms", 0);
+        let b = miner.add("Connected to db-2 in 40ms", 1);
+        let c = miner.add("User bob logged out", 2);
+        let d = miner.add("Connected to cache in 3ms", 3);
+
+        assert_eq!(a, b);
+        assert_eq!(a, d);
+        assert_ne!(a, c);
+
+        let templates = miner.into_templates();
+        assert_eq!(templates[a].text(), "Connected to <*> in <num>ms");
+        assert_eq!(templates[a].count(), 3);
+        assert_eq!(templates[a].first(), 0);
+        assert_eq!(templates[a].last(), 3);
+        assert!(matches(&templates[a].text(), "Connected to queue in 7ms"));
+        assert!(!matches(&templates[a].text(), "Connected to queue in 7ms again"));
+        assert!(!matches(&templates[c].text(), "User bob logged in"));
+
+        assert_eq!(
+            rare_records(&templates, Rarity::Count(1)).into_iter().collect::<Vec<_>>(),
+            vec![2]
+        );
+        assert_eq!(rare_records(&templates, Rarity::Percentile(25.0)).len(), 1);
+        assert!(rare_records(&templates, Rarity::Percentile(10.0)).is_empty());
+        assert_eq!(Rarity::parse("1%"), Some(Rarity::Percentile(1.0)));
+        assert_eq!(Rarity::parse("3"), Some(Rarity::Count(3)));
+        assert_eq!(Rarity::parse("x"), None);
+    }
+
+    #[test]
+    fn test_records_in_baseline() {
+        let (_good, good) = open_test_log("10:00:01 request 17 ok\n10:00:02 cache warm\n");
+        let (_bad, bad) = open_test_log("11:30:00 request 99 ok\n11:30:01 disk full\n11:30:02 cache warm\n");
+        assert_eq!(records_in_baseline(&bad, &good), vec![0, 2]);
+    }
+}
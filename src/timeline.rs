@@ -0,0 +1,146 @@
+use chrono::{Duration, NaiveDateTime};
+
+use crate::log_file::LogFile;
+use crate::log_format::Level;
+
+/// Bars are split into these level groups when stacked
+pub const LEVEL_GROUPS: usize = 3;
+pub const GROUP_ERROR: usize = 0;
+pub const GROUP_WARN: usize = 1;
+pub const GROUP_OTHER: usize = 2;
+
+#[derive(Debug, Clone, Default)]
+pub struct Bucket {
+    /// Records per level group (error, warn, other)
+    pub counts: [usize; LEVEL_GROUPS],
+    /// First line falling into the bucket
+    pub first_line: Option<usize>,
+}
+
+impl Bucket {
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+}
+
+/// Message rate over time of the visible records
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    pub start: NaiveDateTime,
+    pub bucket_size: Duration,
+    pub buckets: Vec<Bucket>,
+    pub selected: usize,
+    pub stacked: bool,
+}
+
+impl Timeline {
+    /// Bucket the visible records of `lf` by timestamp into at most `width`
+    /// buckets. Returns None when no visible record has a timestamp.
+    pub fn build(lf: &LogFile, width: usize, stacked: bool) -> Option<Timeline> {
+        let format = lf.format()?;
+
+        let mut points: Vec<(usize, NaiveDateTime, Option<Level>)> = Vec::new();
+        let mut start = 0;
+        while start < lf.total_lines() {
+            if lf.is_line_visible(start) {
+                if let Some(record) = lf.get_line(start).and_then(|line| format.parse(line)) {
+                    if let Some(ts) = record.timestamp {
+                        points.push((start, ts, record.level));
+                    }
+                }
+            }
+            start = lf.record_end(start);
+        }
+
+        let first = points.iter().map(|(_, ts, _)| *ts).min()?;
+        let last = points.iter().map(|(_, ts, _)| *ts).max()?;
+
+        let width = width.max(1) as u64;
+        let span_ms = (last - first).num_milliseconds() as u64 + 1;
+        // Whole seconds per bucket, so the axis stays readable
+        let bucket_ms = span_ms.div_ceil(width).div_ceil(1000) * 1000;
+        let bucket_size = Duration::milliseconds(bucket_ms as i64);
+        let count = span_ms.div_ceil(bucket_ms);
+
+        let mut buckets = vec![Bucket::default(); count as usize];
+        for (line, ts, level) in points {
+            let index = ((ts - first).num_milliseconds() as u64 / bucket_ms) as usize;
+            let bucket = &mut buckets[index.min(count as usize - 1)];
+            let group = match level {
+                Some(Level::Error) | Some(Level::Fatal) => GROUP_ERROR,
+                Some(Level::Warn) => GROUP_WARN,
+                _ => GROUP_OTHER,
+            };
+            bucket.counts[group] += 1;
+            bucket.first_line = Some(bucket.first_line.map_or(line, |first| first.min(line)));
+        }
+
+        Some(Timeline {
+            start: first,
+            bucket_size,
+            buckets,
+            selected: 0,
+            stacked,
+        })
+    }
+
+    pub fn bucket_start(&self, index: usize) -> NaiveDateTime {
+        self.start + self.bucket_size * index as i32
+    }
+
+    /// Move the selection by `delta` buckets and return the first line of the
+    /// newly selected bucket, skipping over empty buckets
+    pub fn move_selection(&mut self, delta: isize) -> Option<usize> {
+        let mut index = self.selected;
+        loop {
+            let next = index.checked_add_signed(delta)?;
+            if next >= self.buckets.len() {
+                return None;
+            }
+            index = next;
+            if let Some(line) = self.buckets[index].first_line {
+                self.selected = index;
+                return Some(line);
+            }
+        }
+    }
+
+    /// Select the bucket holding `line`, if any
+    pub fn select_line(&mut self, line: usize) {
+        if let Some(index) = self
+            .buckets
+            .iter()
+            .rposition(|b| b.first_line.is_some_and(|first| first <= line))
+        {
+            self.selected = index;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_file::testing::open_test_log;
+
+    #[test]
+    fn test_build() {
+        let content: String = [("00", "info"), ("01", "error"), ("09", "warn"), ("09", "info")]
+            .iter()
+            .map(|(ts, level)| {
+                format!("{{\"ts\":\"2024-01-02T10:00:{}Z\",\"level\":\"{}\",\"msg\":\"x\"}}\n", ts, level)
+            })
+            .collect();
+        let (_file, lf) = open_test_log(&content);
+
+        let mut timeline = Timeline::build(&lf, 5, true).unwrap();
+        assert_eq!(timeline.bucket_size, Duration::seconds(2));
+        assert_eq!(timeline.buckets.len(), 5);
+        assert_eq!(timeline.buckets[0].counts, [1, 0, 1]);
+        assert_eq!(timeline.buckets[4].counts, [0, 1, 1]);
+        assert_eq!(timeline.buckets[4].first_line, Some(2));
+
+        assert_eq!(timeline.move_selection(1), Some(2));
+        assert_eq!(timeline.selected, 4);
+        assert_eq!(timeline.move_selection(1), None);
+    }
+}